@@ -282,6 +282,12 @@ impl WireRead for Current {
     }
 }
 
+impl WireWrite for Current {
+    fn write_to(&self, w: &mut Writer) -> Result<(), WriteError> {
+        self.0.write_to(w)
+    }
+}
+
 impl fmt::Debug for Current {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // native: 1 mA
@@ -297,10 +303,21 @@ impl_std_ops!(Current);
 #[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct DriveTemperature(pub i16);
 
+impl DriveTemperature {
+    #[must_use]
+    pub const fn from_celsius_f64(celsius: f64) -> Self {
+        Self((celsius * 10f64) as i16)
+    }
+
+    #[must_use]
+    pub fn to_celsius_f64(self) -> f64 {
+        f64::from(self.0) / 10.0
+    }
+}
+
 impl fmt::Debug for DriveTemperature {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let degrees = f64::from(self.0) / 10.0;
-        fmt_scaled(f, degrees, &[("°C", 1.0)])
+        fmt_scaled(f, self.to_celsius_f64(), &[("°C", 1.0)])
     }
 }
 
@@ -310,10 +327,21 @@ impl_std_ops!(DriveTemperature);
 #[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MotorTemperature(pub i16);
 
+impl MotorTemperature {
+    #[must_use]
+    pub const fn from_celsius_f64(celsius: f64) -> Self {
+        Self(((celsius + 50f64) * (51f64 / 50f64)) as i16)
+    }
+
+    #[must_use]
+    pub fn to_celsius_f64(self) -> f64 {
+        -50.0 + (f64::from(self.0) * (50.0 / 51.0))
+    }
+}
+
 impl fmt::Debug for MotorTemperature {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let degrees = -50.0 + (f64::from(self.0) * (50.0 / 51.0));
-        fmt_scaled(f, degrees, &[("°C", 1.0)])
+        fmt_scaled(f, self.to_celsius_f64(), &[("°C", 1.0)])
     }
 }
 
@@ -448,6 +476,15 @@ mod tests {
         assert_eq!(format!("{:?}", MotorTemperature(72)), "20.588°C");
     }
 
+    #[test]
+    fn test_temperature_from_celsius() {
+        assert_eq!(DriveTemperature::from_celsius_f64(33.5), DriveTemperature(335));
+        assert_eq!(MotorTemperature::from_celsius_f64(-50.0), MotorTemperature(0));
+        assert_eq!(MotorTemperature::from_celsius_f64(0.0), MotorTemperature(51));
+        assert_eq!(DriveTemperature(335).to_celsius_f64(), 33.5);
+        assert_eq!(MotorTemperature(51).to_celsius_f64(), 0.0);
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn test_serde_round_trip() {
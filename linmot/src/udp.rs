@@ -136,6 +136,53 @@ impl Response {
         })
     }
 
+    fn flags(&self) -> ResponseFlags {
+        let mut f = ResponseFlags::empty();
+
+        f.set(ResponseFlags::STATUS_FLAGS, self.status_flags.is_some());
+        f.set(ResponseFlags::STATE, self.raw_state.is_some());
+        f.set(ResponseFlags::ACTUAL_POSITION, self.actual_position.is_some());
+        f.set(ResponseFlags::DEMAND_POSITION, self.demand_position.is_some());
+        f.set(ResponseFlags::CURRENT, self.current.is_some());
+        f.set(ResponseFlags::WARNING_FLAGS, self.warning_flags.is_some());
+        f.set(ResponseFlags::ERROR_CODE, self.raw_error_code.is_some());
+        f.set(ResponseFlags::MONITORING_CHANNEL, self.monitoring_channel.is_some());
+        f.set(ResponseFlags::REALTIME_CONFIGURATION, self.realtime_configuration.is_some());
+
+        f
+    }
+
+    /// Serializes the response into the provided output buffer, in the same form the drive sends it.
+    ///
+    /// # Errors
+    /// Returns an error if the output buffer is too small to fit the encoded response.
+    pub fn to_wire(&self, out: &mut [u8]) -> Result<usize, WriteError> {
+        let mut w = Writer::new(out);
+
+        // Only the realtime configuration flag of the request affects how the response is read back.
+        let mut request_flags = RequestFlags::empty();
+        request_flags.set(RequestFlags::REALTIME_CONFIGURATION, self.realtime_configuration.is_some());
+
+        request_flags.bits().write_to(&mut w)?;
+        self.flags().bits().write_to(&mut w)?;
+
+        Self::write_opt(&mut w, self.status_flags.map(|f| f.bits()))?;
+        Self::write_opt(&mut w, self.raw_state)?;
+        Self::write_opt(&mut w, self.actual_position)?;
+        Self::write_opt(&mut w, self.demand_position)?;
+        Self::write_opt(&mut w, self.current)?;
+        Self::write_opt(&mut w, self.warning_flags.map(|f| f.bits()))?;
+        Self::write_opt(&mut w, self.raw_error_code)?;
+        Self::write_opt(&mut w, self.monitoring_channel)?;
+        Self::write_opt(&mut w, self.realtime_configuration)?;
+
+        Ok(w.pos())
+    }
+
+    fn write_opt<T: WireWrite>(w: &mut Writer, value: Option<T>) -> Result<(), WriteError> {
+        if let Some(value) = value { value.write_to(w) } else { Ok(()) }
+    }
+
     fn read_opt<T: WireRead>(
         rd: &mut Reader,
         flags: ResponseFlags,
@@ -176,3 +223,37 @@ impl WireRead for RealtimeConfiguration {
         Ok(Self { command, params })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_round_trips_through_wire_format() {
+        let response = Response {
+            status_flags: Some(StatusFlags::from_bits_truncate(0x4A37)),
+            raw_state: Some(0x08C1),
+            actual_position: Some(Position(1_234_567)),
+            demand_position: Some(Position(-7_654_321)),
+            current: Some(Current(-1500)),
+            warning_flags: None,
+            raw_error_code: Some(0x0021),
+            monitoring_channel: Some((1, 0xFFFF_FFFE, 3, 4)),
+            realtime_configuration: None,
+        };
+
+        let mut buffer = [0u8; BUFFER_SIZE];
+        let length = response.to_wire(&mut buffer).unwrap();
+        let parsed = Response::from_wire(&buffer[..length]).unwrap();
+
+        assert_eq!(parsed.status_flags, response.status_flags);
+        assert_eq!(parsed.raw_state, response.raw_state);
+        assert_eq!(parsed.actual_position, response.actual_position);
+        assert_eq!(parsed.demand_position, response.demand_position);
+        assert_eq!(parsed.current, response.current);
+        assert_eq!(parsed.warning_flags, None);
+        assert_eq!(parsed.raw_error_code, response.raw_error_code);
+        assert_eq!(parsed.monitoring_channel, response.monitoring_channel);
+        assert!(parsed.realtime_configuration.is_none());
+    }
+}
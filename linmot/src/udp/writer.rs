@@ -110,3 +110,12 @@ impl WireWrite for i32 {
         w.write_i32_le(*self)
     }
 }
+
+impl<T: WireWrite> WireWrite for (T, T, T, T) {
+    fn write_to(&self, w: &mut Writer) -> Result<()> {
+        self.0.write_to(w)?;
+        self.1.write_to(w)?;
+        self.2.write_to(w)?;
+        self.3.write_to(w)
+    }
+}
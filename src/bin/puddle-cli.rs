@@ -5,7 +5,8 @@
 use anyhow::Result;
 use clap::Parser;
 use puddle::messages::{
-    ClientMessage, CommandUpdate, CoreMessage, DriveState, MotionAction, MotionCommand, MotionCommandFields,
+    ClientMessage, CommandUnits, CommandUpdate, CoreMessage, DriveState, MotionAction, MotionCommand,
+    MotionCommandFields,
 };
 use puddle::units::{Acceleration, Position, Velocity};
 use puddle::{ControllerId, CoreState, SystemLimits};
@@ -234,12 +235,14 @@ impl App {
                         velocity: Velocity(self.inputs[1]),
                         acceleration: Acceleration(self.inputs[2]),
                         deceleration: Acceleration(self.inputs[3]),
+                        dwell: 0,
                     };
                     let cmd2 = MotionCommand {
                         position: Position(self.inputs[4]),
                         velocity: Velocity(self.inputs[5]),
                         acceleration: Acceleration(self.inputs[6]),
                         deceleration: Acceleration(self.inputs[7]),
+                        dwell: 0,
                     };
                     let seq = self.next_seq();
                     self.send(ClientMessage::UpsertCommandSet {
@@ -247,6 +250,8 @@ impl App {
                         set: None,
                         base_version: None,
                         commands: vec![cmd, cmd2],
+                        caps: None,
+                        units: CommandUnits::Absolute,
                     });
                 }
             }
@@ -338,12 +343,14 @@ impl App {
                         velocity: Velocity(self.inputs[1]),
                         acceleration: Acceleration(self.inputs[2]),
                         deceleration: Acceleration(self.inputs[3]),
+                        dwell: 0,
                     };
                     let cmd2 = MotionCommand {
                         position: Position(self.inputs[4]),
                         velocity: Velocity(self.inputs[5]),
                         acceleration: Acceleration(self.inputs[6]),
                         deceleration: Acceleration(self.inputs[7]),
+                        dwell: 0,
                     };
                     let seq = self.next_seq();
                     self.send(ClientMessage::UpsertCommandSet {
@@ -351,6 +358,8 @@ impl App {
                         set: None,
                         base_version: None,
                         commands: vec![cmd, cmd2],
+                        caps: None,
+                        units: CommandUnits::Absolute,
                     });
                 }
             }
@@ -530,12 +539,14 @@ impl App {
                     velocity: Velocity(self.inputs[1]),
                     acceleration: Acceleration(self.inputs[2]),
                     deceleration: Acceleration(self.inputs[3]),
+                    dwell: 0,
                 };
                 let cmd2 = MotionCommand {
                     position: Position(self.inputs[4]),
                     velocity: Velocity(self.inputs[5]),
                     acceleration: Acceleration(self.inputs[6]),
                     deceleration: Acceleration(self.inputs[7]),
+                    dwell: 0,
                 };
                 let seq = self.next_seq();
                 self.send(ClientMessage::UpsertCommandSet {
@@ -543,6 +554,8 @@ impl App {
                     set: None,
                     base_version: None,
                     commands: vec![cmd, cmd2],
+                    caps: None,
+                    units: CommandUnits::Absolute,
                 });
             }
         }
@@ -1,36 +1,113 @@
 use crate::CoreEvent;
 use crate::metrics::Record;
 use anyhow::Result;
-use linmot::mci::units::{Acceleration, Current, DriveTemperature, MotorTemperature, Position, Velocity};
+use linmot::mci::units::{Acceleration, Current, DriveTemperature, Jerk, MotorTemperature, Position, Velocity};
 use linmot::mci::{Command, ControlFlags, ErrorCode, MotionCommand as MciMotionCommand, State, WarningFlags};
-use linmot::udp::{BUFFER_SIZE, CONTROLLER_PORT, DRIVE_PORT, Request, Response, ResponseFlags};
+use linmot::udp::{Request, Response, ResponseFlags};
 use log::{error, info, trace, warn};
-use puddle::messages::{DriveState, MotionCommand as CoreMotionCommand};
-use std::net::{Ipv4Addr, UdpSocket};
+use puddle::messages::{
+    CurrentLimitAction, CurrentLimitEvent, CurrentLimits, DriveState, GuardViolation,
+    MotionCommand as CoreMotionCommand, MotionFault, PositionGuards, RampLength, SoftRamp,
+};
+use recording::Recorder;
+pub use recording::replay_file;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicU8, Ordering};
-use std::sync::{Arc, Mutex, mpsc};
+use std::sync::{Arc, mpsc};
 use std::time::{Duration, Instant};
+use transport::{Clock, SystemClock, Transport, UdpTransport};
 
-// Control handle held by both threads.
-// TODO: Consider flattening this, the separate actions isn't as important now, and we'd like some
-//       more consistent handling. One specific goal is that controller disconnection should be a
-//       controlled stop and then power off once the motion is complete.
-#[derive(Clone, Default)]
+mod recording;
+#[cfg(test)]
+mod simulation;
+mod snapshot;
+mod transport;
+
+// Control handle held by the core thread.
+// TODO: Controller disconnection should be a controlled stop and then power off once the motion is complete.
 pub struct DriveInterface {
-    pub commands: Arc<Mutex<DriveCommands>>,
-    pub actions: Arc<DriveActions>,
+    commands: DriveCommands,
+    publisher: snapshot::Writer<DriveCommands>,
+    actions: Arc<DriveActions>,
+}
+
+impl DriveInterface {
+    /// Modify the drive commands and publish the result to the drive thread.
+    pub fn update_commands(&mut self, update: impl FnOnce(&mut DriveCommands)) {
+        update(&mut self.commands);
+        self.publisher.publish(&self.commands);
+    }
+
+    /// The drive commands as last published.
+    pub fn commands(&self) -> &DriveCommands {
+        &self.commands
+    }
+
+    /// Send one-off action bits to the drive thread. Multiple calls accumulate.
+    pub fn send_actions(&self, bits: u8) {
+        self.actions.send(bits);
+    }
+}
+
+// Drive thread's end of the control handle. Never blocks the real-time loop.
+pub struct DriveLink {
+    commands: snapshot::Reader<DriveCommands>,
+    actions: Arc<DriveActions>,
+}
+
+fn interface() -> (DriveInterface, DriveLink) {
+    let (publisher, reader) = snapshot::channel(DriveCommands::default());
+    let actions = Arc::new(DriveActions::default());
+
+    (
+        DriveInterface { commands: DriveCommands::default(), publisher, actions: actions.clone() },
+        DriveLink { commands: reader, actions },
+    )
 }
 
 // Core->Drive communication
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DriveCommands {
     pub power_enabled: bool,
     pub motion_enabled: bool,
     pub commands: Vec<CoreMotionCommand>,
+    // Passes through the commands before finishing, None to repeat until stopped.
+    pub repetitions: Option<u32>,
+    // Where to go once finished, instead of holding on the last target.
+    pub park: Option<CoreMotionCommand>,
+    // Manual motion, which takes over from the command set while set.
+    pub manual: Option<ManualMotion>,
+    pub guards: PositionGuards,
+    pub current_limits: CurrentLimits,
+    pub soft_ramp: SoftRamp,
+    // Shrink the stroke with the soft ramp, ready to stop.
+    pub soft_stop: bool,
+    // Time over which a crossfade blends from the finished leg's velocity and accelerations to the new commands'.
+    pub crossfade: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ManualMotion {
+    // Move towards the command's target, stopping if `refresh` has not changed within JOG_TIMEOUT.
+    Jog { command: CoreMotionCommand, refresh: u32 },
+    // Move to the command's target and hold there.
+    MoveTo(CoreMotionCommand),
 }
 
+// How long a jog keeps moving without being refreshed by the core.
+pub const JOG_TIMEOUT: Duration = Duration::from_millis(250);
+
+// Drive loop ticks of feedback that the handoff to the next command is predicted ahead by.
+pub const HANDOFF_TICKS: u32 = 3;
+
 pub const ACTION_RESET_INDEX: u8 = 1 << 0;
 pub const ACTION_ACK_ERROR: u8 = 1 << 1;
+// Finish the current leg before carrying on with new commands, instead of resetting the index. Falls back to a reset
+// when not moving.
+pub const ACTION_CROSSFADE: u8 = 1 << 2;
+// Motion is being enabled again to carry on to the interrupted target, which is approached with the resume profile.
+pub const ACTION_RESUME: u8 = 1 << 3;
 
 // Core->Drive one-off actions
 #[derive(Default)]
@@ -40,12 +117,12 @@ pub struct DriveActions {
 
 impl DriveActions {
     /// Core thread: set action bits. Multiple calls accumulate.
-    pub fn send(&self, bits: u8) {
+    fn send(&self, bits: u8) {
         self.pending.fetch_or(bits, Ordering::Release);
     }
 
     /// Drive thread: atomically read and clear all pending actions.
-    pub fn take(&self) -> u8 {
+    fn take(&self) -> u8 {
         self.pending.swap(0, Ordering::AcqRel)
     }
 }
@@ -68,6 +145,84 @@ pub struct DriveFeedback {
     pub error_code: ErrorCode,
     pub drive_temperature: DriveTemperature,
     pub motor_temperature: MotorTemperature,
+    pub remaining_repetitions: Option<u32>,
+    // The active command as currently executed, after slew limiting.
+    pub effective_command: Option<CoreMotionCommand>,
+    // All repetitions are done, and the drive has settled at its final position.
+    pub program_complete: bool,
+    // Set once the position guards have stopped the drive, until the index is reset.
+    pub guard_violation: Option<GuardViolation>,
+    // Set once the motion monitor has stopped the drive, until the index is reset.
+    pub motion_fault: Option<MotionFault>,
+    // Count of times the current limits have been exceeded on this connection, and the latest.
+    pub current_limit_events: u32,
+    pub current_limit_event: Option<CurrentLimitEvent>,
+    // Stroke amplitude and velocity scaling from the soft ramp, as a percentage.
+    pub amplitude: u16,
+    // A soft stop has shrunk the stroke as far as it goes, and motion can be stopped.
+    pub soft_stopped: bool,
+}
+
+// Drive loop configuration, fixed for the lifetime of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    pub interval: Duration,
+    pub overshoot_margin: Position,
+    pub hard_deceleration_min: Acceleration,
+    pub hard_deceleration_max: Acceleration,
+    pub slew_rates: SlewRates,
+    pub motion_monitor: MotionMonitor,
+    pub resume_profile: ResumeProfile,
+}
+
+// How motion comes back up to speed when resumed: the interrupted target is approached with the velocity and
+// accelerations capped, then the commands ramp back to their own values over a number of passes through the set.
+// A zero velocity disables the profile.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResumeProfile {
+    pub velocity: Velocity,
+    pub acceleration: Acceleration,
+    pub ramp_cycles: u32,
+}
+
+// Progress through the resume profile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resuming {
+    // Heading for the target of this command index at the capped values.
+    Approach { index: usize },
+    // Legs started while ramping back up to the command values, and the command index of the one underway.
+    Ramp { legs: u32, index: usize },
+}
+
+// Progress through a crossfade onto new commands.
+#[derive(Debug, Clone, PartialEq)]
+enum Crossfade {
+    // Finishing the leg that was running when the new commands arrived.
+    Finishing { leg: CoreMotionCommand },
+    // Blending from the finished leg's velocity and accelerations to the new commands' since the given time.
+    Blending { from: CoreMotionCommand, since: Instant },
+}
+
+// Limits for how far the drive may fall behind its demand position before motion is stopped, well before
+// the drive's own lag error. A zero distance or time disables that check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct MotionMonitor {
+    pub following_error: Position,
+    pub following_error_time: Duration,
+    pub stall_time: Duration,
+}
+
+// Below this demand velocity, the drive isn't expected to be visibly moving.
+const STALL_VELOCITY: Velocity = Velocity::from_millimeters_per_second(5);
+// Moving less than this counts as not moving at all.
+const STALL_DISTANCE: Position = Position::from_millimeters_f64(0.1);
+
+// Maximum rates of change for edits to the commands while moving, zero for no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SlewRates {
+    pub position: Velocity,
+    pub velocity: Acceleration,
+    pub acceleration: Jerk,
 }
 
 pub struct ConnectionManager {
@@ -77,16 +232,12 @@ pub struct ConnectionManager {
 impl ConnectionManager {
     pub fn new(
         address: String,
-        interval: Duration,
-        overshoot_margin: Position,
-        hard_deceleration_min: Acceleration,
-        hard_deceleration_max: Acceleration,
+        settings: Settings,
+        recording_directory: Option<PathBuf>,
         core_sender: mpsc::Sender<CoreEvent>,
         metrics_sender: Option<mpsc::Sender<Record>>,
     ) -> Self {
-        let interface = DriveInterface::default();
-
-        let connection_interface = interface.clone();
+        let (interface, mut link) = interface();
 
         std::thread::spawn(move || {
             if let Err(e) = thread_priority::set_thread_priority_and_policy(
@@ -113,17 +264,8 @@ impl ConnectionManager {
                 // Reset the feedback state on each connection attempt.
                 let _ = core_sender.send(CoreEvent::DriveStateUpdated(DriveFeedback::default()));
 
-                let mut connection = match Connection::new(
-                    &address,
-                    interval,
-                    overshoot_margin,
-                    hard_deceleration_min,
-                    hard_deceleration_max,
-                    core_sender.clone(),
-                    metrics_sender.clone(),
-                    connection_interface.clone(),
-                ) {
-                    Ok(connection) => connection,
+                let transport = match UdpTransport::connect(&address, settings.interval / 2) {
+                    Ok(transport) => transport,
                     Err(e) => {
                         error!("Failed to connect to drive: {}, trying again in {:?}", e, retry_time);
                         std::thread::sleep(retry_time);
@@ -132,7 +274,17 @@ impl ConnectionManager {
                     }
                 };
 
-                if let Err(e) = connection.run_loop() {
+                let mut connection =
+                    Connection::new(transport, SystemClock, settings, core_sender.clone(), metrics_sender.clone());
+
+                if let Some(directory) = &recording_directory {
+                    match Recorder::create(directory, &settings) {
+                        Ok(recorder) => connection.record_to(recorder),
+                        Err(e) => error!("Failed to start drive session recording: {}", e),
+                    }
+                }
+
+                if let Err(e) = connection.run_loop(&mut link) {
                     error!("Error in drive loop: {}", e);
                 }
             }
@@ -142,16 +294,22 @@ impl ConnectionManager {
     }
 }
 
-pub struct Connection {
+pub struct Connection<T: Transport, C: Clock> {
+    transport: T,
+    clock: C,
+    // When this tick's exchange was sent, as recorded. Timers read this rather than the clock, so that every
+    // decision in a tick is made at the same time, and a replay makes it at the same time again.
+    now: Instant,
     interval: Duration,
     overshoot_margin: Position,
     hard_deceleration_min: Acceleration,
     hard_deceleration_max: Acceleration,
+    slew_rates: SlewRates,
+    motion_monitor: MotionMonitor,
+    resume_profile: ResumeProfile,
     core_sender: mpsc::Sender<CoreEvent>,
     metrics_sender: Option<mpsc::Sender<Record>>,
-    interface: DriveInterface,
-    socket: UdpSocket,
-    buffer: [u8; BUFFER_SIZE],
+    recorder: Option<Recorder>,
     last_rtt: Option<Duration>,
     control_flags: ControlFlags,
     next_motion_command: Option<MciMotionCommand>,
@@ -163,41 +321,90 @@ pub struct Connection {
     // None on the first command, after a reset, or for zero-length legs. When None,
     // clamp_deceleration falls back to the velocity/displacement sign heuristic.
     active_approach_direction: Option<i32>,
+    // When the active command's dwell at its target is over, None unless dwelling.
+    dwell_until: Option<Instant>,
+    resuming: Option<Resuming>,
+    crossfade: Option<Crossfade>,
+    // Time the crossfade blends over, as last copied from the core.
+    crossfade_duration: Duration,
+    // The commands as last copied from the core.
+    requested_commands: Vec<CoreMotionCommand>,
+    // The commands being executed, which follow the requested ones within the slew rates.
     input_commands: Vec<CoreMotionCommand>,
+    repetitions: Option<u32>,
+    park: Option<CoreMotionCommand>,
+    manual: Option<ManualMotion>,
+    // The last jog refresh value seen, and when it was first seen.
+    jog_refresh: Option<(u32, Instant)>,
+    guards: PositionGuards,
+    // Whether the drive was somewhere the guards don't allow last tick, None before the first position.
+    guard_outside: Option<bool>,
+    guard_violation: Option<GuardViolation>,
+    // When the following error first went over the limit.
+    following_error_since: Option<Instant>,
+    // When and where the actual position was last seen to move while the demand position was moving.
+    stall_anchor: Option<(Instant, Position)>,
+    motion_fault: Option<MotionFault>,
+    current_limits: CurrentLimits,
+    // When the current first went over the limits.
+    current_over_since: Option<Instant>,
+    current_limit_events: u32,
+    current_limit_event: Option<CurrentLimitEvent>,
+    // Stopped by the current limits, until the index is reset.
+    current_limit_stopped: bool,
+    soft_ramp: SoftRamp,
+    soft_stop: bool,
+    // Scale of each command's distance from the centre of the stroke, and of its velocity.
+    amplitude: f64,
+    // The active command index as of the last amplitude step.
+    amplitude_index: usize,
+    // Passes through the commands since the index was last reset.
+    completed_repetitions: u32,
+    program_complete: bool,
+    // Generation of the drive commands snapshot last copied in, None until the first copy.
+    input_generation: Option<u64>,
     last_request: Request,
     last_response: Response,
     last_command_index: usize,
     last_state: State,
 }
 
-impl Connection {
+impl<T: Transport, C: Clock> Connection<T, C> {
     pub fn new(
-        address: &str,
-        interval: Duration,
-        overshoot_margin: Position,
-        hard_deceleration_min: Acceleration,
-        hard_deceleration_max: Acceleration,
+        transport: T,
+        clock: C,
+        settings: Settings,
         core_sender: mpsc::Sender<CoreEvent>,
         metrics_sender: Option<mpsc::Sender<Record>>,
-        interface: DriveInterface,
-    ) -> Result<Self> {
-        info!("Connecting to drive at {}:{}...", address, DRIVE_PORT);
+    ) -> Self {
+        let Settings {
+            interval,
+            overshoot_margin,
+            hard_deceleration_min,
+            hard_deceleration_max,
+            slew_rates,
+            motion_monitor,
+            resume_profile,
+        } = settings;
 
-        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, CONTROLLER_PORT))?;
+        // TODO: Send a number of RealtimeConfiguration commands to check the monitoring channels configuration.
 
-        socket.connect((address, DRIVE_PORT))?;
-        socket.set_read_timeout(Some(interval / 2))?;
+        // TODO: Validate other drive configuration too, such as the forced control flags.
 
-        let mut connection = Self {
+        Self {
+            now: clock.now(),
+            transport,
+            clock,
             interval,
             overshoot_margin,
             hard_deceleration_min,
             hard_deceleration_max,
+            slew_rates,
+            motion_monitor,
+            resume_profile,
             core_sender,
             metrics_sender,
-            interface,
-            socket,
-            buffer: [0u8; BUFFER_SIZE],
+            recorder: None,
             last_rtt: None,
             control_flags: ControlFlags::empty(),
             next_motion_command: None,
@@ -206,67 +413,90 @@ impl Connection {
             active_command_index: 0,
             active_command_has_approached: false,
             active_approach_direction: None,
+            dwell_until: None,
+            resuming: None,
+            crossfade: None,
+            crossfade_duration: Duration::ZERO,
+            requested_commands: Vec::new(),
             input_commands: Vec::new(),
+            repetitions: None,
+            park: None,
+            manual: None,
+            jog_refresh: None,
+            guards: PositionGuards::default(),
+            guard_outside: None,
+            guard_violation: None,
+            following_error_since: None,
+            stall_anchor: None,
+            motion_fault: None,
+            current_limits: CurrentLimits::default(),
+            current_over_since: None,
+            current_limit_events: 0,
+            current_limit_event: None,
+            current_limit_stopped: false,
+            soft_ramp: SoftRamp::default(),
+            soft_stop: false,
+            amplitude: 1.0,
+            amplitude_index: 0,
+            completed_repetitions: 0,
+            program_complete: false,
+            input_generation: None,
             last_request: Request::default(),
             last_response: Response::default(),
             last_command_index: 0,
             last_state: State::NotReadyToSwitchOn,
-        };
-
-        // Send a packet to check the drive is responding.
-        connection.send_request(&Request::default())?;
-
-        info!("Connected to drive at {:?} from {:?}", connection.socket.peer_addr()?, connection.socket.local_addr()?);
-
-        // TODO: Send a number of RealtimeConfiguration commands to check the monitoring channels configuration.
-
-        // TODO: Validate other drive configuration too, such as the forced control flags.
+        }
+    }
 
-        Ok(connection)
+    /// Record every exchange and core input from now on.
+    pub fn record_to(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
     }
 
     fn send_request(&mut self, request: &Request) -> Result<Response> {
-        let now = Instant::now();
+        let now = self.clock.now();
+        self.now = now;
         self.last_rtt = None;
 
-        trace!("Sending request: {:?}", request);
-
-        let to_send = request.to_wire(&mut self.buffer)?;
-        self.socket.send(&self.buffer[..to_send])?;
-
-        let received = self.socket.recv(&mut self.buffer)?;
-        let response = Response::from_wire(&self.buffer[..received])?;
+        let response = self.transport.exchange(request)?;
 
-        trace!("Received response: {:?}", response);
+        self.last_rtt = Some(self.clock.now().duration_since(now));
 
-        self.last_rtt = Some(now.elapsed());
+        if let Some(recorder) = &mut self.recorder {
+            recorder.exchange(now, request, &response);
+        }
 
         Ok(response)
     }
 
-    fn run_loop(&mut self) -> Result<()> {
+    fn run_loop(&mut self, link: &mut DriveLink) -> Result<()> {
         loop {
-            let start = Instant::now();
-            self.loop_tick()?;
+            self.run_cycle(link)?;
+        }
+    }
 
-            self.record_metrics(start.elapsed());
+    // One loop tick, followed by waiting out the remainder of the interval.
+    fn run_cycle(&mut self, link: &mut DriveLink) -> Result<()> {
+        let start = self.clock.now();
+        self.loop_tick(link)?;
 
-            let next = start + self.interval;
-            let now = Instant::now();
-            if let Some(sleep_time) = next.checked_duration_since(now) {
-                // Use sleep_until when it is stabilized.
-                std::thread::sleep(sleep_time);
-            } else {
-                warn!("Drive loop running slow! Late by {:?}", now.duration_since(next));
-                // TODO: Do we need to consider anything other than just immediately going again?
-                //       We used to wait for the next interval, but that had longer gaps between
-                //       commands. Our motion prediction may be very off with this approach?
-                continue;
-            }
+        self.record_metrics(self.clock.now().duration_since(start));
+
+        let next = start + self.interval;
+        let now = self.clock.now();
+        if let Some(sleep_time) = next.checked_duration_since(now) {
+            self.clock.sleep(sleep_time);
+        } else {
+            warn!("Drive loop running slow! Late by {:?}", now.duration_since(next));
+            // TODO: Do we need to consider anything other than just immediately going again?
+            //       We used to wait for the next interval, but that had longer gaps between
+            //       commands. Our motion prediction may be very off with this approach?
         }
+
+        Ok(())
     }
 
-    fn loop_tick(&mut self) -> Result<()> {
+    fn loop_tick(&mut self, link: &mut DriveLink) -> Result<()> {
         // 1. Send the current computed state to the drive
 
         let request = Request {
@@ -305,7 +535,9 @@ impl Connection {
                 drive_state: match state {
                     State::ReadyToSwitchOn => DriveState::Off,
                     State::OperationEnabled { homed: true, .. } => {
-                        if self.motion_enabled {
+                        if self.manual.is_some() {
+                            DriveState::Manual
+                        } else if self.motion_enabled {
                             DriveState::Moving
                         } else {
                             DriveState::Paused
@@ -324,6 +556,15 @@ impl Connection {
                 error_code,
                 drive_temperature: DriveTemperature(drive_temperature as i16),
                 motor_temperature: MotorTemperature(motor_temperature as i16),
+                remaining_repetitions: self.remaining_repetitions(),
+                effective_command: self.input_commands.get(self.active_command_index).cloned(),
+                program_complete: self.program_complete,
+                guard_violation: self.guard_violation,
+                motion_fault: self.motion_fault,
+                current_limit_events: self.current_limit_events,
+                current_limit_event: self.current_limit_event,
+                amplitude: (self.amplitude * 100.0).round() as u16,
+                soft_stopped: self.soft_stop && self.amplitude <= self.soft_ramp_initial(),
             };
 
             self.core_sender.send(CoreEvent::DriveStateUpdated(feedback))?;
@@ -331,25 +572,85 @@ impl Connection {
 
         // 3. Read any new instructions from the core
 
-        let actions = self.interface.actions.take();
+        let mut actions = link.actions.take();
+        if actions != 0 {
+            if let Some(recorder) = &mut self.recorder {
+                recorder.actions(actions);
+            }
+        }
+        if (actions & ACTION_CROSSFADE) != 0 {
+            // Carry on with the leg underway, only picking up the new commands once it is done.
+            let leg = self.input_commands.get(self.active_command_index).filter(|_| self.motion_enabled);
+            self.crossfade = leg.map(|leg| Crossfade::Finishing { leg: leg.clone() });
+            if self.crossfade.is_some() {
+                self.completed_repetitions = 0;
+                self.program_complete = false;
+            } else {
+                actions |= ACTION_RESET_INDEX;
+            }
+        }
         if (actions & ACTION_RESET_INDEX) != 0 {
             self.active_command_index = 0;
+            self.crossfade = None;
+            self.resuming = None;
             self.active_command_has_approached = false;
             self.active_approach_direction = None;
+            self.dwell_until = None;
+            self.completed_repetitions = 0;
+            self.program_complete = false;
+            self.guard_violation = None;
+            self.motion_fault = None;
+            self.current_limit_stopped = false;
+            // Starting over begins from the bottom of the soft ramp.
+            self.amplitude = 0.0;
+            self.amplitude_index = 0;
         }
         if (actions & ACTION_ACK_ERROR) != 0 {
             self.control_flags.insert(ControlFlags::ERROR_ACKNOWLEDGE);
         }
+        if (actions & ACTION_RESUME) != 0 {
+            // Resuming acknowledges a current limit pause, the same as starting over.
+            self.current_limit_stopped = false;
+            if self.resume_profile.velocity.0 > 0 {
+                self.resuming = Some(Resuming::Approach { index: self.active_command_index });
+            }
+        }
 
-        // If we can't take the lock, we'll just try again next time
-        if let Ok(shared) = self.interface.commands.try_lock() {
+        // Only copy the commands when a new snapshot has been published (or on a new connection).
+        link.commands.poll();
+        if self.input_generation != Some(link.commands.generation()) {
+            let shared = link.commands.latest();
             self.power_enabled = shared.power_enabled;
             self.motion_enabled = shared.motion_enabled;
-            self.input_commands.clone_from(&shared.commands);
+            self.repetitions = shared.repetitions;
+            self.park.clone_from(&shared.park);
+            self.manual.clone_from(&shared.manual);
+            self.current_limits.clone_from(&shared.current_limits);
+            self.soft_ramp = shared.soft_ramp;
+            self.soft_stop = shared.soft_stop;
+            self.crossfade_duration = shared.crossfade;
+            if self.guards != shared.guards {
+                // Finding itself somewhere newly disallowed isn't the drive moving there.
+                self.guards.clone_from(&shared.guards);
+                self.guard_outside = None;
+            }
+            self.requested_commands.clone_from(&shared.commands);
+            self.input_generation = Some(link.commands.generation());
+
+            if let Some(recorder) = &mut self.recorder {
+                recorder.commands(shared);
+            }
+
+            trace!("Drive commands updated to generation {}", link.commands.generation());
         }
 
         // 4. Compute the next motion command
+        self.ramp_amplitude();
+        self.slew_input_commands();
         self.compute_next_request()?;
+        self.monitor_motion();
+        self.limit_current();
+        self.enforce_guards();
 
         Ok(())
     }
@@ -399,6 +700,47 @@ impl Connection {
 
         let next_command_count = motion_command_count.wrapping_add(1) & 0xF;
 
+        if let Some(manual) = &self.manual {
+            let now = self.now;
+
+            let command = match manual {
+                ManualMotion::Jog { command, refresh } => {
+                    if self.jog_refresh.map(|(last, _)| last) != Some(*refresh) {
+                        self.jog_refresh = Some((*refresh, now));
+                    }
+
+                    let alive = self.jog_refresh.is_some_and(|(_, at)| now.duration_since(at) < JOG_TIMEOUT);
+                    if alive && command.velocity.0 != 0 { Some(command) } else { None }
+                }
+                ManualMotion::MoveTo(command) => Some(command),
+            };
+
+            let command = match command {
+                Some(command) => Command::VaiGoToPos {
+                    target_position: command.position,
+                    maximal_velocity: command.velocity,
+                    acceleration: command.acceleration,
+                    deceleration: clamp_deceleration(
+                        demand_position,
+                        demand_velocity,
+                        command.position,
+                        command.deceleration,
+                        None,
+                        self.overshoot_margin,
+                        self.hard_deceleration_min,
+                        self.hard_deceleration_max,
+                    ),
+                },
+                None => Command::VaiStop { deceleration: self.hard_deceleration_min },
+            };
+
+            self.next_motion_command = Some(MciMotionCommand { count: next_command_count, command });
+
+            return Ok(());
+        }
+
+        self.jog_refresh = None;
+
         if self.input_commands.is_empty() {
             self.next_motion_command = Some(MciMotionCommand {
                 count: next_command_count,
@@ -408,9 +750,50 @@ impl Connection {
         }
 
         self.active_command_index = self.active_command_index % self.input_commands.len();
-        let input_command = self.input_commands.get(self.active_command_index).unwrap();
+        let finishing = matches!(self.crossfade, Some(Crossfade::Finishing { .. }));
+        let input_command = match &self.crossfade {
+            Some(Crossfade::Finishing { leg }) => leg,
+            _ => self.input_commands.get(self.active_command_index).unwrap(),
+        };
         let current_target = input_command.position;
 
+        // Once the final repetition is done, go to the park position (or stay on the last target)
+        // and hold there until the index is reset.
+        if self.remaining_repetitions() == Some(0) {
+            let end_command = self.park.as_ref().unwrap_or(input_command).clone();
+
+            self.program_complete = demand_velocity.0 == 0 && demand_position == end_command.position;
+
+            let command = if self.motion_enabled {
+                Command::VaiGoToPos {
+                    target_position: end_command.position,
+                    maximal_velocity: end_command.velocity,
+                    acceleration: end_command.acceleration,
+                    deceleration: clamp_deceleration(
+                        demand_position,
+                        demand_velocity,
+                        end_command.position,
+                        end_command.deceleration,
+                        None,
+                        self.overshoot_margin,
+                        self.hard_deceleration_min,
+                        self.hard_deceleration_max,
+                    ),
+                }
+            } else {
+                Command::VaiStop { deceleration: self.hard_deceleration_min }
+            };
+
+            self.next_motion_command = Some(MciMotionCommand { count: next_command_count, command });
+
+            return Ok(());
+        }
+
+        // The last command of the final repetition is a stop, like a dwelling command.
+        let final_command = !finishing
+            && self.remaining_repetitions() == Some(1)
+            && self.active_command_index == self.input_commands.len() - 1;
+
         // Determine the approach direction for this command: the direction from the
         // previous waypoint to this one. This is used by `clamp_deceleration()` to
         // distinguish a genuine overshoot from a benign mid-stroke reversal state.
@@ -439,18 +822,39 @@ impl Connection {
         //    advance when a new target starts within range of the current position.
         //    (a) and (b) are fallbacks for cases where prediction is unreliable, e.g.
         //    after parameter changes or at very low velocities.
-        let target_reached = {
+        //
+        // While motion is disabled the active command is held, so a paused stroke resumes
+        // toward the same target rather than treating the stop as an arrival.
+        //
+        // A command with a dwell time is never handed off early: it is reached once the drive
+        // has stopped exactly on the target and held it there for the dwell time.
+        let target_reached = if !self.motion_enabled {
+            // Once stopped, forget the approach so that accelerating away from a standstill on
+            // resume isn't mistaken for having arrived. An interrupted dwell starts again.
+            if demand_velocity.0 == 0 {
+                self.active_command_has_approached = false;
+            }
+            self.dwell_until = None;
+
+            false
+        } else {
             let displacement = current_target.0 as i64 - demand_position.0 as i64;
             let dist = displacement.unsigned_abs();
             let v = demand_velocity.0 as i64;
 
+            let a = demand_acceleration.0 as i64;
+
             let moving_toward = match approach_direction {
                 Some(dir) if dir != 0 => (dir > 0 && v > 0) || (dir < 0 && v < 0),
                 _ => (displacement > 0 && v > 0) || (displacement < 0 && v < 0),
             };
+            let decelerating = (v > 0 && a < 0) || (v < 0 && a > 0);
 
             // Record that we've committed to this command (observed moving toward target).
-            if moving_toward {
+            // Decelerating toward the target doesn't count: straight after an early handoff through
+            // a pass-through waypoint, that is the previous leg's braking carrying over, and treating
+            // it as an approach makes the predicted stop below skip this waypoint entirely.
+            if moving_toward && !decelerating {
                 self.active_command_has_approached = true;
             }
 
@@ -459,7 +863,7 @@ impl Connection {
                 // usable on a later cycle. Look ahead by three controller ticks so we bias
                 // slightly early rather than occasionally missing the handoff and creating a
                 // brief zero-acceleration plateau between commands.
-                let handoff_horizon = self.interval.saturating_mul(3);
+                let handoff_horizon = self.interval.saturating_mul(HANDOFF_TICKS);
                 let next_position =
                     predict_position(demand_position, demand_velocity, demand_acceleration, handoff_horizon);
                 let distance_after = current_target.0 as i64 - next_position.0 as i64;
@@ -468,9 +872,8 @@ impl Connection {
                 // Also fire if the drive is decelerating toward the target and predicted to
                 // be closer than it is now but still short — i.e. about to stop near the target.
                 // Use the same three-cycle horizon so slightly-early handoff wins over a visible gap.
-                let a = demand_acceleration.0 as i64;
                 let next_v = predict_velocity(demand_velocity, demand_acceleration, handoff_horizon).0 as i64;
-                let decelerating_toward = moving_toward && ((v > 0 && a < 0) || (v < 0 && a > 0));
+                let decelerating_toward = moving_toward && decelerating;
                 let will_stop_or_reverse = decelerating_toward && ((v > 0 && next_v <= 0) || (v < 0 && next_v >= 0));
 
                 // Safety gate for speculative crossing handoff:
@@ -497,7 +900,18 @@ impl Connection {
                 (crosses && can_recover_with_hard_decel) || (self.active_command_has_approached && will_stop_or_reverse)
             };
 
-            if dist == 0 {
+            if input_command.dwell > 0 || final_command {
+                if dist == 0 && v == 0 {
+                    let now = self.now;
+                    let until =
+                        *self.dwell_until.get_or_insert(now + Duration::from_millis(u64::from(input_command.dwell)));
+                    now >= until
+                } else {
+                    // Not (or no longer) holding the target, e.g. it was edited mid-dwell.
+                    self.dwell_until = None;
+                    false
+                }
+            } else if dist == 0 {
                 true
             } else if self.active_command_has_approached && (v == 0 || !moving_toward) {
                 // Stopped or moving away after having approached: arrived.
@@ -513,13 +927,33 @@ impl Connection {
             }
         };
 
-        let (mut clamp_target, mut clamp_approach_direction) = if target_reached {
+        if target_reached && !finishing && self.active_command_index == self.input_commands.len() - 1 {
+            self.completed_repetitions = self.completed_repetitions.saturating_add(1);
+        }
+
+        // Don't advance past the end of the final repetition.
+        let target_reached = target_reached && self.remaining_repetitions() != Some(0);
+
+        let (mut clamp_target, mut clamp_approach_direction) = if target_reached && finishing {
+            // The leg is done, carry on with the new commands from where it finished.
+            let from = input_command.clone();
+            self.active_command_index = crossfade_index(&self.input_commands, current_target);
+            self.active_command_has_approached = false;
+            self.dwell_until = None;
+            self.crossfade = Some(Crossfade::Blending { from, since: self.now });
+
+            let next_target = self.input_commands[self.active_command_index].position;
+            self.active_approach_direction = normalize_direction((next_target.0 - current_target.0).signum());
+
+            (current_target, approach_direction)
+        } else if target_reached {
             let prev_index = (self.active_command_index + self.input_commands.len() - 1) % self.input_commands.len();
             let prev_target = self.input_commands[prev_index].position;
 
             // Advance to next command.
             self.active_command_index = (self.active_command_index + 1) % self.input_commands.len();
             self.active_command_has_approached = false;
+            self.dwell_until = None;
             let next_command = self.input_commands.get(self.active_command_index).unwrap();
 
             // The new approach direction is from `current_target` toward the next target.
@@ -536,19 +970,27 @@ impl Connection {
         };
 
         // Re-fetch after possible advance.
-        let input_command = self.input_commands.get(self.active_command_index).unwrap();
+        let input_command = match &self.crossfade {
+            Some(Crossfade::Finishing { leg }) => leg.clone(),
+            _ => self.input_commands[self.active_command_index].clone(),
+        };
+        let input_command = self.crossfaded(input_command);
+        let input_command = self.resume_limited(input_command);
 
         // Keep previous-waypoint clamping alive across early handoff until the new leg is
         // actually approached. This preserves smooth command pipelining while retaining
-        // the old bound during pipeline delay and momentum carry-over.
-        (clamp_target, clamp_approach_direction) = effective_clamp_target_after_handoff(
-            &self.input_commands,
-            self.active_command_index,
-            self.active_command_has_approached,
-            self.active_approach_direction,
-            clamp_target,
-            clamp_approach_direction,
-        );
+        // the old bound during pipeline delay and momentum carry-over. A crossfade didn't
+        // come from the previous waypoint, so keeps to the target it has just reached.
+        if self.crossfade.is_none() {
+            (clamp_target, clamp_approach_direction) = effective_clamp_target_after_handoff(
+                &self.input_commands,
+                self.active_command_index,
+                self.active_command_has_approached,
+                self.active_approach_direction,
+                clamp_target,
+                clamp_approach_direction,
+            );
+        }
 
         if !self.motion_enabled {
             let deceleration = clamp_deceleration(
@@ -592,6 +1034,300 @@ impl Connection {
         Ok(())
     }
 
+    // Cap the command while coming back up to speed after resuming. Each leg after the interrupted one lifts the
+    // cap a step, reaching the command's own values after the ramp's passes through the set.
+    fn resume_limited(&mut self, command: CoreMotionCommand) -> CoreMotionCommand {
+        let legs = match self.resuming {
+            None => return command,
+            Some(Resuming::Approach { index }) if index == self.active_command_index => None,
+            Some(Resuming::Approach { .. }) => Some(1),
+            Some(Resuming::Ramp { legs, index }) if index == self.active_command_index => Some(legs),
+            Some(Resuming::Ramp { legs, .. }) => Some(legs + 1),
+        };
+
+        let progress = match legs {
+            None => 0.0,
+            Some(legs) => {
+                let ramp_legs = self.resume_profile.ramp_cycles.saturating_mul(self.input_commands.len() as u32);
+                if legs >= ramp_legs {
+                    self.resuming = None;
+                    return command;
+                }
+
+                self.resuming = Some(Resuming::Ramp { legs, index: self.active_command_index });
+                f64::from(legs) / f64::from(ramp_legs)
+            }
+        };
+
+        let limit = |value: i32, cap: i32| {
+            if value <= cap { value } else { cap + (f64::from(value - cap) * progress) as i32 }
+        };
+
+        CoreMotionCommand {
+            velocity: Velocity(limit(command.velocity.0, self.resume_profile.velocity.0)),
+            acceleration: Acceleration(limit(command.acceleration.0, self.resume_profile.acceleration.0)),
+            deceleration: Acceleration(limit(command.deceleration.0, self.resume_profile.acceleration.0)),
+            ..command
+        }
+    }
+
+    // Blend from the finished leg's velocity and accelerations to the command's over the crossfade duration.
+    fn crossfaded(&mut self, command: CoreMotionCommand) -> CoreMotionCommand {
+        let Some(Crossfade::Blending { from, since }) = &self.crossfade else {
+            return command;
+        };
+
+        let progress = self.now.saturating_duration_since(*since).div_duration_f64(self.crossfade_duration);
+        if progress.is_nan() || progress >= 1.0 {
+            self.crossfade = None;
+            return command;
+        }
+
+        let blend = |from: i32, to: i32| from + (f64::from(to - from) * progress).round() as i32;
+
+        CoreMotionCommand {
+            velocity: Velocity(blend(from.velocity.0, command.velocity.0)),
+            acceleration: Acceleration(blend(from.acceleration.0, command.acceleration.0)),
+            deceleration: Acceleration(blend(from.deceleration.0, command.deceleration.0)),
+            ..command
+        }
+    }
+
+    // The amplitude the soft ramp starts from and shrinks back to, or full amplitude without a soft ramp.
+    fn soft_ramp_initial(&self) -> f64 {
+        if self.soft_ramp.is_enabled() { f64::from(self.soft_ramp.initial.min(100)) / 100.0 } else { 1.0 }
+    }
+
+    // Grow the amplitude towards full while moving, or shrink it back while soft stopping. A ramp over passes
+    // through the set steps the amplitude each time a new command becomes active.
+    fn ramp_amplitude(&mut self) {
+        let initial = self.soft_ramp_initial();
+        let next_command = self.amplitude_index != self.active_command_index;
+        self.amplitude_index = self.active_command_index;
+
+        if self.motion_enabled && initial < 1.0 {
+            let fraction = match self.soft_ramp.length {
+                RampLength::Duration(duration) => self.interval.as_secs_f64() * 1_000.0 / f64::from(duration),
+                RampLength::Repetitions(repetitions) if next_command => {
+                    1.0 / (f64::from(repetitions) * self.input_commands.len().max(1) as f64)
+                }
+                RampLength::Repetitions(_) => 0.0,
+            };
+            let step = fraction * (1.0 - initial);
+            self.amplitude += if self.soft_stop { -step } else { step };
+        }
+
+        self.amplitude = self.amplitude.clamp(initial, 1.0);
+    }
+
+    // Move the executing commands towards the requested ones scaled by the amplitude, limited by the slew rates.
+    // A different number of commands is a new set, and that or any edit while not moving applies immediately.
+    fn slew_input_commands(&mut self) {
+        // The centre of the stroke that the amplitude scales the positions around.
+        let (lowest, highest) = self
+            .requested_commands
+            .iter()
+            .fold((i32::MAX, i32::MIN), |(lowest, highest), c| (lowest.min(c.position.0), highest.max(c.position.0)));
+        let centre = (f64::from(lowest) + f64::from(highest)) / 2.0;
+        let amplitude = self.amplitude;
+        let scaled = |command: &CoreMotionCommand| CoreMotionCommand {
+            position: Position((centre + (f64::from(command.position.0) - centre) * amplitude).round() as i32),
+            velocity: Velocity((f64::from(command.velocity.0) * amplitude).round() as i32),
+            ..command.clone()
+        };
+
+        // A crossfade takes the place of the slew rates, heading for the new commands once the leg is done.
+        let finishing = matches!(self.crossfade, Some(Crossfade::Finishing { .. }));
+        if !self.motion_enabled || finishing || self.input_commands.len() != self.requested_commands.len() {
+            self.input_commands.clear();
+            self.input_commands.extend(self.requested_commands.iter().map(scaled));
+            return;
+        }
+
+        // All the rates are in units of 1/10th of their value's unit per second.
+        let seconds = self.interval.as_secs_f64() * 10.0;
+        let (position_step, velocity_step, acceleration_step) = (
+            f64::from(self.slew_rates.position.0) * seconds,
+            f64::from(self.slew_rates.velocity.0) * seconds,
+            f64::from(self.slew_rates.acceleration.0) * seconds,
+        );
+
+        for (current, requested) in self.input_commands.iter_mut().zip(&self.requested_commands) {
+            let requested = scaled(requested);
+            current.position.0 = slew(current.position.0, requested.position.0, position_step);
+            current.velocity.0 = slew(current.velocity.0, requested.velocity.0, velocity_step);
+            current.acceleration.0 = slew(current.acceleration.0, requested.acceleration.0, acceleration_step);
+            current.deceleration.0 = slew(current.deceleration.0, requested.deceleration.0, acceleration_step);
+            current.dwell = requested.dwell;
+        }
+    }
+
+    // Stop the drive gently if it isn't keeping up with its demand position, which is most likely an obstruction.
+    fn monitor_motion(&mut self) {
+        let (
+            Some(State::OperationEnabled { homed: true, .. }),
+            Some(actual_position),
+            Some(demand_position),
+            Some((demand_velocity, _, _, _)),
+        ) = (
+            self.last_response.state(),
+            self.last_response.actual_position,
+            self.last_response.demand_position,
+            self.last_response.monitoring_channel,
+        )
+        else {
+            self.following_error_since = None;
+            self.stall_anchor = None;
+            return;
+        };
+
+        let now = self.now;
+        let MotionMonitor { following_error, following_error_time, stall_time } = self.motion_monitor;
+
+        let error = Position(demand_position.0.saturating_sub(actual_position.0));
+        if following_error.0 > 0 && error.0.unsigned_abs() > following_error.0.unsigned_abs() {
+            let since = *self.following_error_since.get_or_insert(now);
+            if now.duration_since(since) >= following_error_time && self.motion_fault.is_none() {
+                warn!("Stopping drive, following error of {:?}", error);
+                self.motion_fault = Some(MotionFault::FollowingError { error });
+            }
+        } else {
+            self.following_error_since = None;
+        }
+
+        let demand_moving = (demand_velocity as i32).unsigned_abs() >= STALL_VELOCITY.0.unsigned_abs();
+        if !stall_time.is_zero() && demand_moving {
+            match self.stall_anchor {
+                Some((since, position)) if (actual_position.0 - position.0).abs() < STALL_DISTANCE.0 => {
+                    if now.duration_since(since) >= stall_time && self.motion_fault.is_none() {
+                        warn!("Stopping drive, stalled at {:?}", actual_position);
+                        self.motion_fault = Some(MotionFault::Stall { position: actual_position });
+                    }
+                }
+                _ => self.stall_anchor = Some((now, actual_position)),
+            }
+        } else {
+            self.stall_anchor = None;
+        }
+
+        if self.motion_fault.is_some() {
+            if let Some(motion_command) = &mut self.next_motion_command {
+                motion_command.command = Command::VaiStop { deceleration: self.hard_deceleration_min };
+            }
+        }
+    }
+
+    // Act on the motor current staying over the limits, as a software force limit.
+    fn limit_current(&mut self) {
+        let (Some(State::OperationEnabled { homed: true, .. }), Some(actual_position), Some(current)) =
+            (self.last_response.state(), self.last_response.actual_position, self.last_response.current)
+        else {
+            self.current_over_since = None;
+            return;
+        };
+
+        let now = self.now;
+        let limit = self.current_limits.limit_at(actual_position, current);
+
+        match limit.filter(|limit| current.0.unsigned_abs() > limit.0.unsigned_abs()) {
+            Some(limit) if !self.current_limit_stopped => {
+                let since = *self.current_over_since.get_or_insert(now);
+                if now.duration_since(since) >= Duration::from_millis(u64::from(self.current_limits.time)) {
+                    self.current_over_since = None;
+
+                    let reversed = self.current_limits.action == CurrentLimitAction::Reverse
+                        && self.reverse_away_from(actual_position, current);
+                    let action = if reversed {
+                        CurrentLimitAction::Reverse
+                    } else {
+                        self.current_limit_stopped = true;
+                        CurrentLimitAction::Pause
+                    };
+
+                    let event = CurrentLimitEvent { current, limit, position: actual_position, action };
+                    warn!("Current limit exceeded: {:?}", event);
+
+                    self.current_limit_events = self.current_limit_events.wrapping_add(1);
+                    self.current_limit_event = Some(event);
+                }
+            }
+            _ => self.current_over_since = None,
+        }
+
+        if self.current_limit_stopped {
+            if let Some(motion_command) = &mut self.next_motion_command {
+                motion_command.command = Command::VaiStop { deceleration: self.hard_deceleration_min };
+            }
+        }
+    }
+
+    // Skip ahead to the next command with a target on the other side of the position from where the current is
+    // pushing. Returns false if there isn't one, or the command set isn't running.
+    fn reverse_away_from(&mut self, position: Position, current: Current) -> bool {
+        if self.manual.is_some() || !self.motion_enabled || self.input_commands.is_empty() {
+            return false;
+        }
+
+        let count = self.input_commands.len();
+        let away = (1..=count).map(|offset| (self.active_command_index + offset) % count).find(|&index| {
+            let displacement = i64::from(self.input_commands[index].position.0) - i64::from(position.0);
+            displacement * i64::from(current.0.signum()) < 0
+        });
+
+        let Some(index) = away else {
+            return false;
+        };
+
+        self.active_command_index = index;
+        self.active_command_has_approached = false;
+        self.active_approach_direction = None;
+        self.dwell_until = None;
+        self.crossfade = None;
+
+        true
+    }
+
+    // Stop the drive if it is about to head somewhere it must not go, or has already got there.
+    fn enforce_guards(&mut self) {
+        let (Some(demand_position), Some(actual_position)) =
+            (self.last_response.demand_position, self.last_response.actual_position)
+        else {
+            return;
+        };
+
+        // Only moving into a disallowed position is a violation, so that the drive can be moved out of one.
+        let outside = [demand_position, actual_position].into_iter().find(|&p| !self.guards.allows(p));
+        if let Some(position) = outside {
+            if self.guard_outside == Some(false) && self.guard_violation.is_none() {
+                warn!("Drive moved to {:?}, outside of the position guards", position);
+                self.guard_violation = Some(GuardViolation::Position { position });
+            }
+        }
+        self.guard_outside = Some(outside.is_some());
+
+        let Some(motion_command) = &mut self.next_motion_command else {
+            return;
+        };
+
+        // Only the moves the guards disallow are stopped, and the violation stands until the drive is back inside
+        // them and given one they allow.
+        if let Command::VaiGoToPos { target_position, .. } = motion_command.command {
+            if !self.guards.allows_move(demand_position, target_position) {
+                if self.guard_violation.is_none() {
+                    warn!("Refusing to move to {:?}, outside of the position guards", target_position);
+                    self.guard_violation = Some(GuardViolation::Target { position: target_position });
+                }
+                motion_command.command = Command::VaiStop { deceleration: self.hard_deceleration_min };
+            } else if outside.is_none() {
+                self.guard_violation = None;
+            }
+        }
+    }
+
+    fn remaining_repetitions(&self) -> Option<u32> {
+        self.repetitions.map(|repetitions| repetitions.saturating_sub(self.completed_repetitions))
+    }
+
     fn record_metrics(&self, loop_duration: Duration) {
         let Some(sender) = &self.metrics_sender else {
             return;
@@ -643,6 +1379,31 @@ pub fn predict_velocity(velocity: Velocity, acceleration: Acceleration, duration
     Velocity((v0 + a * t_us / 100_000) as i32)
 }
 
+// Step `current` towards `target` by at most `max_step`, or straight to it if there is no limit.
+fn slew(current: i32, target: i32, max_step: f64) -> i32 {
+    if max_step <= 0.0 {
+        return target;
+    }
+
+    // Always make some progress, however short the interval.
+    let max_step = max_step.max(1.0) as i64;
+    let step = (i64::from(target) - i64::from(current)).clamp(-max_step, max_step);
+
+    (i64::from(current) + step) as i32
+}
+
+// Index of the command to carry on with once a crossfade's leg has finished at the position: the one after the
+// command with the nearest target, as though the commands had been running all along.
+fn crossfade_index(commands: &[CoreMotionCommand], position: Position) -> usize {
+    let nearest = commands
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, command)| (i64::from(command.position.0) - i64::from(position.0)).unsigned_abs())
+        .map_or(0, |(index, _)| index);
+
+    (nearest + 1) % commands.len().max(1)
+}
+
 fn normalize_direction(direction: i32) -> Option<i32> {
     match direction.signum() {
         0 => None,
@@ -808,7 +1569,10 @@ fn log_state_change(old_state: &mut State, new_state: State) {
 
 #[cfg(test)]
 mod tests {
+    use super::simulation::{Harness, INTERVAL, SETTINGS, Sample};
     use super::*;
+    use linmot::mci::units::Current;
+    use puddle::messages::{CurrentLimitZone, KeepOutZone, SoftRamp, WorkCoordinates};
     use std::time::Duration;
 
     fn cmd(position_mm: i32) -> CoreMotionCommand {
@@ -817,6 +1581,7 @@ mod tests {
             velocity: Velocity::from_meters_per_second(1),
             acceleration: Acceleration::from_meters_per_second_squared(4),
             deceleration: Acceleration::from_meters_per_second_squared(4),
+            dwell: 0,
         }
     }
 
@@ -1178,4 +1943,749 @@ mod tests {
         // But should NOT be a hard-stop value.
         assert!(result.0 < Acceleration::from_meters_per_second_squared(100).0);
     }
+
+    // --- Connection scenario tests against a simulated drive ---
+
+    fn slow_cmd(position_mm: i32) -> CoreMotionCommand {
+        CoreMotionCommand { velocity: Velocity::from_millimeters_per_second(500), ..cmd(position_mm) }
+    }
+
+    fn mm(position: f64) -> f64 {
+        position * 1000.0
+    }
+
+    // Longest run of consecutive samples at a standstill.
+    fn longest_standstill(history: &[Sample]) -> usize {
+        history
+            .iter()
+            .scan(0, |run, sample| {
+                *run = if sample.velocity == 0.0 { *run + 1 } else { 0 };
+                Some(*run)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn max_position(history: &[Sample]) -> f64 {
+        history.iter().map(|s| s.position).fold(f64::MIN, f64::max)
+    }
+
+    fn min_position(history: &[Sample]) -> f64 {
+        history.iter().map(|s| s.position).fold(f64::MAX, f64::min)
+    }
+
+    #[test]
+    fn simulated_two_point_pattern_reaches_both_ends_without_overshoot() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(1), |h| h.position() >= 0.049_99));
+        let start = harness.history_len();
+        harness.run_for(Duration::from_secs(3));
+        let history = harness.history_since(start);
+
+        assert!((mm(max_position(&history)) - 150.0).abs() < 0.1, "max {}mm", mm(max_position(&history)));
+        assert!((mm(min_position(&history)) - 50.0).abs() < 0.1, "min {}mm", mm(min_position(&history)));
+
+        // Handoff between waypoints should be continuous, not a visible stop.
+        assert!(longest_standstill(&history) <= 1, "standstill of {} ticks", longest_standstill(&history));
+    }
+
+    #[test]
+    fn simulated_cycle_frequency_accounts_for_early_handoffs() {
+        const LIMITS: puddle::SystemLimits = puddle::SystemLimits {
+            position: Position::from_millimeters(360),
+            velocity: Velocity::from_meters_per_second(2),
+            acceleration: Acceleration::from_meters_per_second_squared(16),
+            deceleration: Acceleration::from_meters_per_second_squared(16),
+        };
+        const FREQUENCY: f64 = 1.0;
+
+        // Scaled the way the core does it, carrying on through the middle target each way.
+        let commands = [cmd(50), cmd(100), cmd(150), cmd(100)];
+        let timing = crate::profile::CycleTiming::new(&commands, &LIMITS, INTERVAL).unwrap();
+        let scale = timing.speed_scale(FREQUENCY);
+        let scaled = |value: i32, factor: f64| (f64::from(value) * factor).round() as i32;
+        let mut harness = Harness::new(
+            commands
+                .iter()
+                .map(|c| CoreMotionCommand {
+                    velocity: Velocity(scaled(c.velocity.0, scale)),
+                    acceleration: Acceleration(scaled(c.acceleration.0, scale * scale)),
+                    deceleration: Acceleration(scaled(c.deceleration.0, scale * scale)),
+                    ..c.clone()
+                })
+                .collect(),
+        );
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(1), |h| h.position() >= 0.049_99));
+        harness.run_for(Duration::from_secs(2));
+        let start = harness.history_len();
+        harness.run_for(Duration::from_secs(10));
+        let history = harness.history_since(start);
+
+        // Ticks at which the carriage heads up past the middle target.
+        let crossings: Vec<_> = history
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| pair[0].position < 0.125 && pair[1].position >= 0.125)
+            .map(|(tick, _)| tick)
+            .collect();
+        let cycles = crossings.len() - 1;
+        let period = (crossings[cycles] - crossings[0]) as f64 * INTERVAL.as_secs_f64() / cycles as f64;
+        // Within a couple of ticks a cycle, where timing every leg rest to rest would be eight ticks short.
+        assert!((period - 1.0 / FREQUENCY).abs() <= 2.0 * INTERVAL.as_secs_f64() + 1e-9, "period {period}s");
+    }
+
+    #[test]
+    fn simulated_multi_waypoint_pattern_visits_waypoints_in_order() {
+        let mut harness = Harness::new(vec![cmd(20), cmd(75), cmd(150), cmd(100)]);
+        harness.start();
+        harness.run_for(Duration::from_secs(4));
+
+        let mut targets: Vec<i32> = harness
+            .drive
+            .model
+            .borrow()
+            .commands
+            .iter()
+            .filter_map(|command| match command {
+                Command::VaiGoToPos { target_position, .. } => Some(target_position.0 / 10_000),
+                _ => None,
+            })
+            .collect();
+        targets.dedup();
+
+        assert!(targets.len() >= 8, "only visited {:?}", targets);
+        for window in targets.windows(2) {
+            let expected = match window[0] {
+                20 => 75,
+                75 => 150,
+                150 => 100,
+                100 => 20,
+                other => panic!("unexpected target {}", other),
+            };
+            assert_eq!(window[1], expected, "in {:?}", targets);
+        }
+
+        // 75mm is passed through on the way up, so the drive should not stop there.
+        let history = harness.history_since(0);
+        let crossing = history.windows(2).find(|w| w[0].position < 0.075 && w[1].position >= 0.075).unwrap();
+        assert!(crossing[1].velocity > 0.0);
+    }
+
+    #[test]
+    fn simulated_pause_mid_stroke_holds_then_resumes_to_same_target() {
+        let mut harness = Harness::new(vec![slow_cmd(50), slow_cmd(150)]);
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.position() > 0.1 && h.velocity() > 0.0));
+        let paused_at = harness.position();
+        let paused_index = harness.feedback.active_command_index;
+        assert_eq!(paused_index, 1);
+
+        harness.set_motion_enabled(false);
+        assert!(harness.run_until(Duration::from_secs(1), |h| h.velocity() == 0.0));
+
+        let start = harness.history_len();
+        harness.run_for(Duration::from_millis(500));
+        let held = harness.history_since(start);
+
+        // Stopped with the hard minimum deceleration after up to two cycles of latency, and held position
+        // while paused.
+        let stopping_distance = 0.5 * 0.5 / (2.0 * 7.0) + 0.5 * 2.0 * INTERVAL.as_secs_f64();
+        assert!(harness.position() > paused_at && harness.position() < paused_at + stopping_distance + 0.0001);
+        assert!(held.iter().all(|s| s.velocity == 0.0 && s.position == held[0].position));
+        assert_eq!(harness.feedback.active_command_index, paused_index);
+
+        harness.set_motion_enabled(true);
+        assert!(harness.run_until(Duration::from_secs(1), |h| h.feedback.active_command_index == 0));
+        assert!((mm(max_position(&harness.history_since(start))) - 150.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn simulated_braking_into_a_slow_leg_still_reaches_its_target() {
+        // 100mm is passed through while braking down to the slow leg's velocity, which carries on into that leg.
+        let slow = CoreMotionCommand { velocity: Velocity::from_millimeters_per_second(200), ..cmd(110) };
+        let commands = vec![cmd(20), cmd(100), slow, cmd(150)];
+        let targets: Vec<f64> = commands.iter().map(|command| f64::from(command.position.0) / 1e4).collect();
+        let mut harness = Harness::new(commands);
+        harness.start();
+
+        // Every target is reached before handing off to the next, pass after pass.
+        let mut index = 0;
+        let mut handoffs = 0;
+        for _ in 0..1_500 {
+            harness.tick();
+            if harness.feedback.active_command_index != index {
+                let position = f64::from(harness.feedback.demand_position.0) / 1e4;
+                assert!((position - targets[index]).abs() < 1.0, "left {}mm at {}mm", targets[index], position);
+                index = harness.feedback.active_command_index;
+                handoffs += 1;
+            }
+        }
+        assert!(handoffs >= 8, "only {} handoffs", handoffs);
+    }
+
+    const RESUME_SETTINGS: Settings = Settings {
+        resume_profile: ResumeProfile {
+            velocity: Velocity::from_millimeters_per_second(100),
+            acceleration: Acceleration::from_meters_per_second_squared(1),
+            ramp_cycles: 2,
+        },
+        ..SETTINGS
+    };
+
+    #[test]
+    fn simulated_resume_approaches_slowly_then_ramps_up() {
+        let mut harness = Harness::with_settings(vec![cmd(50), cmd(150)], RESUME_SETTINGS);
+        harness.start();
+
+        assert!(
+            harness.run_until(Duration::from_secs(5), |h| h.feedback.active_command_index == 1 && h.velocity() > 0.5)
+        );
+        harness.set_motion_enabled(false);
+        assert!(harness.run_until(Duration::from_secs(1), |h| h.velocity() == 0.0));
+
+        // The rest of the way to the interrupted target is at the resume velocity.
+        let start = harness.history_len();
+        harness.resume();
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.feedback.active_command_index == 0));
+        let approach = harness.history_since(start);
+        assert!(approach.iter().all(|s| s.velocity.abs() <= 0.1 + 1e-9));
+        assert!((mm(max_position(&approach)) - 150.0).abs() < 0.1);
+
+        // Then the first of the ramp's four legs goes a quarter of the way back up to the command velocity.
+        let start = harness.history_len();
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.feedback.active_command_index == 1));
+        let peak = harness.history_since(start).iter().map(|s| s.velocity.abs()).fold(0.0, f64::max);
+        assert!(peak > 0.3 && peak <= 0.325 + 1e-9, "peak {}", peak);
+
+        // And after two passes runs at the command values again, which peak at sqrt(4 * 0.1) for these legs.
+        assert!(harness.run_until(Duration::from_secs(5), |h| h.velocity() < -0.6));
+    }
+
+    #[test]
+    fn simulated_start_after_pause_skips_resume_profile() {
+        let mut harness = Harness::with_settings(vec![cmd(50), cmd(150)], RESUME_SETTINGS);
+        harness.start();
+
+        assert!(
+            harness.run_until(Duration::from_secs(5), |h| h.feedback.active_command_index == 1 && h.velocity() > 0.5)
+        );
+        harness.set_motion_enabled(false);
+        assert!(harness.run_until(Duration::from_secs(1), |h| h.velocity() == 0.0));
+
+        // Starting over heads straight for the first target at the command values.
+        harness.start();
+        assert!(harness.run_until(Duration::from_millis(500), |h| h.velocity() < -0.25));
+    }
+
+    #[test]
+    fn crossfade_carries_on_after_the_nearest_target() {
+        let commands = [cmd(60), cmd(140), cmd(100)];
+        assert_eq!(crossfade_index(&commands, Position::from_millimeters(150)), 2);
+        assert_eq!(crossfade_index(&commands, Position::from_millimeters(90)), 0);
+        assert_eq!(crossfade_index(&[], Position::default()), 0);
+    }
+
+    #[test]
+    fn simulated_crossfade_finishes_the_leg_then_blends() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.start();
+        assert!(
+            harness.run_until(Duration::from_secs(2), |h| h.feedback.active_command_index == 1 && h.velocity() > 0.5)
+        );
+
+        // Replaced halfway up the stroke with slower commands.
+        let slow = |position_mm| CoreMotionCommand {
+            velocity: Velocity::from_millimeters_per_second(500),
+            ..cmd(position_mm)
+        };
+        harness.update_commands(|shared| {
+            shared.commands = vec![slow(60), slow(140)];
+            shared.crossfade = Duration::from_millis(500);
+        });
+        harness.interface.send_actions(ACTION_CROSSFADE);
+
+        // Still goes all the way up, then carries on down to the target after the nearest one.
+        let start = harness.history_len();
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.velocity() < -0.1));
+        assert_eq!(harness.feedback.active_command_index, 0);
+        assert!((mm(max_position(&harness.history_since(start))) - 150.0).abs() < 0.1);
+
+        // Faster than the new commands at first, then at their velocity once blended.
+        let start = harness.history_len();
+        assert!(harness.run_until(Duration::from_secs(1), |h| h.feedback.active_command_index == 1));
+        assert!(harness.history_since(start).iter().any(|s| s.velocity < -0.55));
+        harness.run_for(Duration::from_millis(500));
+        let start = harness.history_len();
+        harness.run_for(Duration::from_secs(1));
+        let blended = harness.history_since(start);
+        let (lowest, highest) = (mm(min_position(&blended)), mm(max_position(&blended)));
+        assert!(blended.iter().all(|s| s.velocity.abs() <= 0.5 + 1e-9));
+        assert!((lowest - 60.0).abs() < 0.1 && (highest - 140.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn simulated_soft_ramp_grows_and_shrinks_the_stroke() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.update_commands(|shared| {
+            shared.soft_ramp = SoftRamp { initial: 20, length: RampLength::Duration(2_000) };
+        });
+        harness.start();
+
+        // Starts with a short stroke around the centre, at a fraction of the velocity.
+        let start = harness.history_len();
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.feedback.active_command_index == 1));
+        assert!(mm(harness.position()) > 75.0 && mm(harness.position()) < 95.0);
+        assert!(harness.history_since(start).iter().all(|s| s.velocity.abs() <= 0.5));
+
+        // Full stroke once the ramp is over.
+        assert!(harness.run_until(Duration::from_secs(3), |h| h.feedback.amplitude == 100));
+        let start = harness.history_len();
+        harness.run_for(Duration::from_secs(1));
+        let full = harness.history_since(start);
+        assert!((mm(min_position(&full)) - 50.0).abs() < 0.1 && (mm(max_position(&full)) - 150.0).abs() < 0.1);
+
+        // A soft stop shrinks it back down before reporting it can stop.
+        harness.update_commands(|shared| shared.soft_stop = true);
+        assert!(!harness.feedback.soft_stopped);
+        assert!(harness.run_until(Duration::from_secs(3), |h| h.feedback.soft_stopped));
+        assert_eq!(harness.feedback.amplitude, 20);
+        let start = harness.history_len();
+        harness.run_for(Duration::from_millis(500));
+        let late = harness.history_since(start);
+        assert!(mm(min_position(&late)) > 85.0 && mm(max_position(&late)) < 115.0);
+    }
+
+    #[test]
+    fn simulated_soft_ramp_over_repetitions_steps_at_each_command() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.update_commands(|shared| {
+            shared.soft_ramp = SoftRamp { initial: 20, length: RampLength::Repetitions(2) };
+        });
+        harness.start();
+
+        // Two passes of two commands each take a quarter of the way from the initial amplitude to full.
+        assert!(harness.run_until(Duration::from_millis(100), |h| h.feedback.amplitude == 20));
+        let mut amplitudes = vec![];
+        assert!(harness.run_until(Duration::from_secs(10), |h| {
+            amplitudes.push(h.feedback.amplitude);
+            h.feedback.amplitude == 100
+        }));
+        amplitudes.dedup();
+        assert_eq!(amplitudes, vec![20, 40, 60, 80, 100]);
+
+        // And back down again the same way for a soft stop.
+        let mut amplitudes = vec![];
+        harness.update_commands(|shared| shared.soft_stop = true);
+        assert!(harness.run_until(Duration::from_secs(10), |h| {
+            amplitudes.push(h.feedback.amplitude);
+            h.feedback.soft_stopped
+        }));
+        amplitudes.dedup();
+        assert_eq!(amplitudes, vec![100, 80, 60, 40, 20]);
+    }
+
+    #[test]
+    fn simulated_dwell_holds_at_target_before_returning() {
+        let mut harness = Harness::new(vec![cmd(50), CoreMotionCommand { dwell: 500, ..cmd(150) }]);
+        harness.start();
+
+        let start = harness.history_len();
+        assert!(
+            harness.run_until(Duration::from_secs(2), |h| h.feedback.active_command_index == 0 && h.velocity() < 0.0)
+        );
+        let history = harness.history_since(start);
+
+        // Came to a full stop on the target rather than handing off early, and held it for the dwell.
+        assert!((mm(max_position(&history)) - 150.0).abs() < 1e-6);
+        let held = history.iter().filter(|s| s.velocity == 0.0 && (mm(s.position) - 150.0).abs() < 1e-6).count();
+        let held_for = SETTINGS.interval * held as u32;
+        assert!(
+            held_for >= Duration::from_millis(500) && held_for <= Duration::from_millis(510),
+            "held for {:?}",
+            held_for
+        );
+
+        // The other end has no dwell, so it is still handed off early without a standstill.
+        let start = harness.history_len();
+        harness.run_for(Duration::from_millis(500));
+        assert!(longest_standstill(&harness.history_since(start)) <= 1);
+    }
+
+    #[test]
+    fn simulated_repetitions_finish_on_last_target() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.update_commands(|shared| shared.repetitions = Some(2));
+        harness.start();
+
+        let start = harness.history_len();
+        assert!(harness.run_until(Duration::from_secs(3), |h| h.feedback.program_complete));
+        assert_eq!(harness.feedback.remaining_repetitions, Some(0));
+        assert!((mm(harness.position()) - 150.0).abs() < 1e-6);
+        assert_eq!(harness.velocity(), 0.0);
+
+        // Turned around at the far end once per pass, the last time coming to a stop.
+        let history = harness.history_since(start);
+        let turns = history.windows(2).filter(|w| w[0].velocity > 0.0 && w[1].velocity <= 0.0).count();
+        assert_eq!(turns, 2);
+
+        // Stays put with motion still enabled, until the index is reset.
+        let start = harness.history_len();
+        harness.run_for(Duration::from_millis(500));
+        assert!(harness.history_since(start).iter().all(|s| s.velocity == 0.0));
+
+        harness.start();
+        harness.run_for(Duration::from_millis(100));
+        assert_eq!(harness.feedback.remaining_repetitions, Some(2));
+        assert!(!harness.feedback.program_complete);
+        assert!(harness.velocity() < 0.0);
+    }
+
+    #[test]
+    fn simulated_repetitions_park_after_finishing() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.update_commands(|shared| {
+            shared.repetitions = Some(1);
+            shared.park = Some(cmd(100));
+        });
+        harness.start();
+
+        let start = harness.history_len();
+        assert!(harness.run_until(Duration::from_secs(3), |h| h.feedback.program_complete));
+        assert!((mm(harness.position()) - 100.0).abs() < 1e-6);
+
+        // Completed the pass by stopping on the last target before heading to the park position.
+        let history = harness.history_since(start);
+        assert!((mm(max_position(&history)) - 150.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn simulated_move_to_overrides_command_set() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.update_commands(|shared| shared.manual = Some(ManualMotion::MoveTo(cmd(120))));
+
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.velocity() == 0.0 && h.position() > 0.1));
+        assert_eq!(harness.feedback.drive_state, puddle::messages::DriveState::Manual);
+        assert!((mm(harness.position()) - 120.0).abs() < 1e-6);
+
+        // Holds there, rather than picking up the command set.
+        let start = harness.history_len();
+        harness.run_for(Duration::from_millis(200));
+        assert!(harness.history_since(start).iter().all(|s| (mm(s.position) - 120.0).abs() < 1e-6));
+    }
+
+    #[test]
+    fn simulated_jog_stops_when_not_refreshed() {
+        let mut harness = Harness::new(vec![]);
+
+        let jog = |refresh| ManualMotion::Jog { command: slow_cmd(350), refresh };
+        harness.update_commands(|shared| shared.manual = Some(jog(0)));
+
+        // Keeps going for as long as it is refreshed.
+        for refresh in 1..=3 {
+            harness.run_for(Duration::from_millis(100));
+            harness.update_commands(|shared| shared.manual = Some(jog(refresh)));
+        }
+        assert!(harness.velocity() > 0.4, "velocity {}", harness.velocity());
+
+        // Then comes to a stop short of the target once the refreshes stop.
+        harness.run_for(JOG_TIMEOUT);
+        assert!(harness.run_until(Duration::from_millis(200), |h| h.velocity() == 0.0));
+        assert!(mm(harness.position()) < 350.0);
+    }
+
+    #[test]
+    fn simulated_keep_out_zone_stops_before_crossing() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.update_commands(|shared| {
+            shared
+                .guards
+                .keep_out
+                .push(KeepOutZone { start: Position::from_millimeters(90), end: Position::from_millimeters(110) });
+        });
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.feedback.guard_violation.is_some()));
+        assert_eq!(
+            harness.feedback.guard_violation,
+            Some(GuardViolation::Target { position: Position::from_millimeters(150) })
+        );
+
+        // Stays stopped short of the zone, even with motion still enabled.
+        harness.run_for(Duration::from_millis(500));
+        assert_eq!(harness.velocity(), 0.0);
+        assert!(mm(max_position(&harness.history_since(0))) < 90.0);
+    }
+
+    #[test]
+    fn simulated_keep_out_zone_can_be_left_but_not_entered() {
+        let mut harness = Harness::new(vec![]);
+        harness.update_commands(|shared| shared.manual = Some(ManualMotion::MoveTo(cmd(120))));
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.velocity() == 0.0 && h.position() > 0.1));
+
+        harness.update_commands(|shared| {
+            shared
+                .guards
+                .keep_out
+                .push(KeepOutZone { start: Position::from_millimeters(100), end: Position::from_millimeters(140) });
+            shared.manual = Some(ManualMotion::MoveTo(cmd(160)));
+        });
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.velocity() == 0.0 && mm(h.position()) > 159.0));
+        assert_eq!(harness.feedback.guard_violation, None);
+
+        harness.update_commands(|shared| shared.manual = Some(ManualMotion::MoveTo(cmd(60))));
+        harness.run_for(Duration::from_millis(500));
+        assert_eq!(
+            harness.feedback.guard_violation,
+            Some(GuardViolation::Target { position: Position::from_millimeters(60) })
+        );
+        assert!((mm(harness.position()) - 160.0).abs() < 1e-6);
+
+        // Moves that stay clear of the zone carry on, and clear the violation.
+        harness.update_commands(|shared| shared.manual = Some(ManualMotion::MoveTo(cmd(180))));
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.velocity() == 0.0 && mm(h.position()) > 179.0));
+        assert_eq!(harness.feedback.guard_violation, None);
+    }
+
+    #[test]
+    fn simulated_following_error_stops_motion() {
+        let monitor = MotionMonitor {
+            following_error: Position::from_millimeters(3),
+            following_error_time: Duration::from_millis(20),
+            stall_time: Duration::ZERO,
+        };
+        let mut harness =
+            Harness::with_settings(vec![cmd(50), cmd(150)], Settings { motion_monitor: monitor, ..SETTINGS });
+        harness.drive.model.borrow_mut().obstruction = Some(0.1);
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.feedback.motion_fault.is_some()));
+        assert!(matches!(
+            harness.feedback.motion_fault,
+            Some(MotionFault::FollowingError { error }) if error > Position::from_millimeters(3)
+        ));
+
+        // Brought to a stop well before the drive would have got to the far end.
+        assert!(harness.run_until(Duration::from_secs(1), |h| h.velocity() == 0.0));
+        assert!(mm(harness.position()) < 150.0);
+    }
+
+    #[test]
+    fn simulated_stall_stops_motion() {
+        let monitor = MotionMonitor {
+            following_error: Position(0),
+            following_error_time: Duration::ZERO,
+            stall_time: Duration::from_millis(50),
+        };
+        let mut harness =
+            Harness::with_settings(vec![slow_cmd(50), slow_cmd(150)], Settings { motion_monitor: monitor, ..SETTINGS });
+        harness.drive.model.borrow_mut().obstruction = Some(0.1);
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.feedback.motion_fault.is_some()));
+        assert_eq!(
+            harness.feedback.motion_fault,
+            Some(MotionFault::Stall { position: Position::from_millimeters(100) })
+        );
+
+        // Cleared by the next start.
+        harness.drive.model.borrow_mut().obstruction = None;
+        harness.start();
+        harness.run_for(Duration::from_millis(100));
+        assert_eq!(harness.feedback.motion_fault, None);
+    }
+
+    #[test]
+    fn simulated_current_limit_reverses_away_from_obstruction() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.update_commands(|shared| {
+            shared.current_limits = CurrentLimits {
+                positive: Some(Current(2_000)),
+                time: 10,
+                action: CurrentLimitAction::Reverse,
+                ..CurrentLimits::default()
+            };
+        });
+        {
+            let mut model = harness.drive.model.borrow_mut();
+            model.obstruction = Some(0.1);
+            model.obstruction_current = 3_000;
+        }
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.feedback.current_limit_events == 2));
+        let event = harness.feedback.current_limit_event.unwrap();
+        assert_eq!(event.action, CurrentLimitAction::Reverse);
+        assert_eq!(event.position, Position::from_millimeters(100));
+
+        // Turned back each time rather than carrying on to the far end, and kept going.
+        assert!(mm(max_position(&harness.history_since(0))) < 130.0);
+        assert_ne!(harness.velocity(), 0.0);
+    }
+
+    #[test]
+    fn simulated_current_limit_pauses_until_reset() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.update_commands(|shared| {
+            shared.current_limits = CurrentLimits {
+                absolute: Some(Current(2_000)),
+                zones: vec![CurrentLimitZone {
+                    start: Position::from_millimeters(90),
+                    end: Position::from_millimeters(110),
+                    limit: Current(500),
+                }],
+                ..CurrentLimits::default()
+            };
+        });
+        {
+            let mut model = harness.drive.model.borrow_mut();
+            model.obstruction = Some(0.1);
+            model.obstruction_current = 1_000;
+        }
+        harness.start();
+
+        // Only the zone's lower limit is exceeded.
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.feedback.current_limit_events == 1));
+        assert_eq!(harness.feedback.current_limit_event.unwrap().limit, Current(500));
+
+        harness.run_for(Duration::from_millis(500));
+        assert_eq!(harness.velocity(), 0.0);
+        assert_eq!(harness.feedback.current_limit_events, 1);
+
+        // Resuming acknowledges the pause and carries on.
+        harness.drive.model.borrow_mut().obstruction = None;
+        harness.resume();
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.position() > 0.149));
+    }
+
+    #[test]
+    fn simulated_current_limit_directions_follow_inverted_coordinates() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+
+        // Pushing up the drive is pushing in the negative work direction.
+        let coordinates = WorkCoordinates { offset: Position::from_millimeters(200), inverted: true };
+        harness.update_commands(|shared| {
+            shared.current_limits = CurrentLimits {
+                positive: Some(Current(4_000)),
+                negative: Some(Current(2_000)),
+                time: 10,
+                ..CurrentLimits::default()
+            }
+            .to_drive(coordinates);
+        });
+        {
+            let mut model = harness.drive.model.borrow_mut();
+            model.obstruction = Some(0.1);
+            model.obstruction_current = 3_000;
+        }
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.feedback.current_limit_events == 1));
+        let event = harness.feedback.current_limit_event.unwrap();
+        assert_eq!((event.current.0 > 0, event.limit), (true, Current(2_000)));
+    }
+
+    #[test]
+    fn simulated_deceleration_edit_while_moving_stays_within_margin() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.position() > 0.1 && h.velocity() > 0.0));
+
+        let start = harness.history_len();
+        harness.update_commands(|shared| {
+            shared.commands[1].deceleration = Acceleration::from_millimeters_per_second_squared(100);
+        });
+        harness.run_for(Duration::from_secs(1));
+
+        let overshoot = mm(max_position(&harness.history_since(start))) - 150.0;
+        assert!(overshoot <= 5.0, "overshot by {}mm", overshoot);
+    }
+
+    #[test]
+    fn simulated_velocity_edit_while_moving_changes_pace() {
+        let mut harness = Harness::new(vec![slow_cmd(50), slow_cmd(350)]);
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.position() > 0.1 && h.velocity() > 0.0));
+
+        harness.update_commands(|shared| {
+            shared.commands[1].velocity = Velocity::from_meters_per_second(1);
+        });
+        harness.run_for(Duration::from_millis(150));
+
+        assert!(harness.velocity() > 0.9, "velocity {}", harness.velocity());
+        assert!(harness.run_until(Duration::from_secs(1), |h| h.feedback.active_command_index == 0));
+        assert!((mm(max_position(&harness.history_since(0))) - 350.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn simulated_edits_while_moving_follow_slew_rates() {
+        let settings = Settings {
+            slew_rates: SlewRates {
+                position: Velocity::from_millimeters_per_second(100),
+                velocity: Acceleration::from_meters_per_second_squared(2),
+                acceleration: Jerk(0),
+            },
+            ..SETTINGS
+        };
+        let mut harness = Harness::with_settings(vec![slow_cmd(50), slow_cmd(350)], settings);
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.feedback.active_command_index == 1));
+
+        harness.update_commands(|shared| {
+            shared.commands[1].position = Position::from_millimeters(300);
+            shared.commands[1].velocity = Velocity::from_meters_per_second(1);
+        });
+
+        // Each tick moves the executed command by no more than the rates allow.
+        let mut last = harness.feedback.effective_command.clone().unwrap();
+        for _ in 0..100 {
+            harness.tick();
+            let effective = harness.feedback.effective_command.clone().unwrap();
+            assert!((last.position.0 - effective.position.0).abs() <= 2_000);
+            assert!((last.velocity.0 - effective.velocity.0).abs() <= 4_000);
+            last = effective;
+        }
+
+        // Part way through both ramps after 200ms.
+        assert!(last.position > Position::from_millimeters(325), "position {:?}", last.position);
+        assert!(last.velocity < Velocity::from_millimeters_per_second(950), "velocity {:?}", last.velocity);
+
+        assert!(harness.run_until(Duration::from_secs(1), |h| h.feedback.effective_command.as_ref().is_some_and(
+            |c| c.position == Position::from_millimeters(300) && c.velocity == Velocity::from_meters_per_second(1)
+        )));
+    }
+
+    #[test]
+    fn simulated_target_moved_behind_recovers_from_overshoot() {
+        let mut harness = Harness::new(vec![cmd(50), cmd(150)]);
+        harness.start();
+
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.position() > 0.1 && h.velocity() > 0.0));
+
+        // Move the target to where the drive is right now, so it is already past it by the time
+        // the new command is applied.
+        let new_target = Position((harness.position() * 1e7) as i32);
+        let start = harness.history_len();
+        harness.update_commands(|shared| shared.commands[1].position = new_target);
+        harness.run_for(Duration::from_millis(300));
+
+        let history = harness.history_since(start);
+        let overshoot = mm(max_position(&history)) - new_target.0 as f64 / 10_000.0;
+        let velocity = history[0].velocity;
+
+        // Bounded by the distance covered at the hard maximum deceleration, plus the margin it scales over.
+        let bound = mm(velocity * velocity / (2.0 * 30.0)) + 5.0;
+        assert!(overshoot > 0.0 && overshoot <= bound, "overshot by {}mm, bound {}mm", overshoot, bound);
+
+        // The pattern carries on afterwards.
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.position() <= 0.050_01));
+        assert!(harness.run_until(Duration::from_secs(2), |h| h.position() >= new_target.0 as f64 * 1e-7 - 0.000_01));
+    }
 }
@@ -0,0 +1,436 @@
+use super::transport::{ManualClock, Transport};
+use super::{Connection, DriveCommands, Settings, interface};
+use anyhow::{Context, Result, anyhow, bail};
+use linmot::udp::{BUFFER_SIZE, Request, Response};
+use log::{error, info, warn};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Drive session recordings.
+//
+// A recording holds every request/response exchanged with the drive, with the time of each exchange,
+// along with the core inputs (drive commands snapshots and one-off actions) as the drive thread
+// consumed them. That is everything `Connection` depends on, as it reads the time once per tick when
+// sending the request, so replaying a recording through the same drive logic must produce the same
+// requests, and any difference points at a behaviour change.
+//
+// File layout, all little-endian:
+//
+//   header:    "PDRC", version (u8), length (u32) and the drive loop settings as JSON
+//   exchange:  tag 1, time in µs since the first exchange (u64), request length (u8) and wire bytes,
+//              response length (u8) and wire bytes
+//   actions:   tag 2, action bits (u8)
+//   commands:  tag 3, length (u32) and the drive commands as JSON
+//
+// Inputs always follow the exchange of the loop tick that consumed them.
+
+const MAGIC: &[u8; 4] = b"PDRC";
+const VERSION: u8 = 1;
+
+const TAG_EXCHANGE: u8 = 1;
+const TAG_ACTIONS: u8 = 2;
+const TAG_COMMANDS: u8 = 3;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+enum Frame {
+    Exchange { time: Duration, request: Request, response: Response },
+    Actions(u8),
+    Commands(DriveCommands),
+}
+
+/// Drive thread's end of a recording. Encoding and file IO happen on a separate writer thread.
+pub struct Recorder {
+    sender: mpsc::Sender<Frame>,
+    start: Option<Instant>,
+}
+
+impl Recorder {
+    /// Start recording to a new file in `directory`.
+    pub fn create(directory: &Path, settings: &Settings) -> Result<Self> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = directory.join(format!("drive-{}.rec", timestamp));
+
+        let file = File::create_new(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+
+        let (sender, receiver) = mpsc::channel();
+        let settings = *settings;
+
+        std::thread::spawn(move || {
+            if let Err(e) = write_recording(BufWriter::new(file), &settings, receiver) {
+                error!("Drive session recording stopped: {}", e);
+            }
+        });
+
+        info!("Recording drive session to {}", path.display());
+
+        Ok(Self::new(sender))
+    }
+
+    fn new(sender: mpsc::Sender<Frame>) -> Self {
+        Self { sender, start: None }
+    }
+
+    pub fn exchange(&mut self, now: Instant, request: &Request, response: &Response) {
+        let start = *self.start.get_or_insert(now);
+
+        self.send(Frame::Exchange {
+            time: now.duration_since(start),
+            request: request.clone(),
+            response: response.clone(),
+        });
+    }
+
+    pub fn actions(&mut self, bits: u8) {
+        self.send(Frame::Actions(bits));
+    }
+
+    pub fn commands(&mut self, commands: &DriveCommands) {
+        self.send(Frame::Commands(commands.clone()));
+    }
+
+    fn send(&self, frame: Frame) {
+        // If the writer thread has stopped, it has already logged why.
+        let _ = self.sender.send(frame);
+    }
+}
+
+fn write_recording(mut out: impl Write, settings: &Settings, receiver: mpsc::Receiver<Frame>) -> Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    let json = serde_json::to_vec(settings)?;
+    out.write_all(&u32::try_from(json.len())?.to_le_bytes())?;
+    out.write_all(&json)?;
+
+    let mut buffer = [0u8; BUFFER_SIZE];
+    let mut last_flush = Instant::now();
+
+    while let Ok(frame) = receiver.recv() {
+        match frame {
+            Frame::Exchange { time, request, response } => {
+                out.write_all(&[TAG_EXCHANGE])?;
+                out.write_all(&u64::try_from(time.as_micros())?.to_le_bytes())?;
+
+                let length = request.to_wire(&mut buffer)?;
+                out.write_all(&[u8::try_from(length)?])?;
+                out.write_all(&buffer[..length])?;
+
+                let length = response.to_wire(&mut buffer)?;
+                out.write_all(&[u8::try_from(length)?])?;
+                out.write_all(&buffer[..length])?;
+            }
+            Frame::Actions(bits) => {
+                out.write_all(&[TAG_ACTIONS, bits])?;
+            }
+            Frame::Commands(commands) => {
+                let json = serde_json::to_vec(&commands)?;
+                out.write_all(&[TAG_COMMANDS])?;
+                out.write_all(&u32::try_from(json.len())?.to_le_bytes())?;
+                out.write_all(&json)?;
+            }
+        }
+
+        // Flush periodically so that a recording survives the process being killed.
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            out.flush()?;
+            last_flush = Instant::now();
+        }
+    }
+
+    out.flush()?;
+
+    Ok(())
+}
+
+/// One loop tick of a recording: the exchange, and the core inputs consumed after it.
+pub struct Cycle {
+    pub time: Duration,
+    pub request: Vec<u8>,
+    pub response: Response,
+    pub commands: Option<DriveCommands>,
+    pub actions: u8,
+}
+
+pub struct Recording {
+    pub settings: Settings,
+    pub cycles: Vec<Cycle>,
+}
+
+impl Recording {
+    pub fn read(mut input: impl Read) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("Not a drive session recording");
+        }
+
+        let version = read_u8(&mut input)?;
+        if version != VERSION {
+            bail!("Unsupported recording version {}", version);
+        }
+
+        let length = u32::from_le_bytes(read_array(&mut input)?) as usize;
+        let settings = serde_json::from_slice(&read_bytes(&mut input, length)?)?;
+
+        let mut cycles: Vec<Cycle> = Vec::new();
+
+        loop {
+            let tag = match read_u8(&mut input) {
+                Ok(tag) => tag,
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            };
+
+            match Self::read_frame(&mut input, tag, &mut cycles) {
+                Ok(()) => {}
+                Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof) => {
+                    // The process was most likely stopped mid-write, keep everything up to here.
+                    warn!("Recording is truncated after {} cycles", cycles.len());
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Self { settings, cycles })
+    }
+
+    fn read_frame(input: &mut impl Read, tag: u8, cycles: &mut Vec<Cycle>) -> Result<()> {
+        if tag == TAG_EXCHANGE {
+            let time = Duration::from_micros(u64::from_le_bytes(read_array(input)?));
+            let length = usize::from(read_u8(input)?);
+            let request = read_bytes(input, length)?;
+            let length = usize::from(read_u8(input)?);
+            let response = Response::from_wire(&read_bytes(input, length)?)?;
+
+            cycles.push(Cycle { time, request, response, commands: None, actions: 0 });
+
+            return Ok(());
+        }
+
+        let cycle = cycles.last_mut().ok_or_else(|| anyhow!("Recording has inputs before the first exchange"))?;
+
+        match tag {
+            TAG_ACTIONS => {
+                cycle.actions |= read_u8(input)?;
+            }
+            TAG_COMMANDS => {
+                let length = u32::from_le_bytes(read_array(input)?) as usize;
+                cycle.commands = Some(serde_json::from_slice(&read_bytes(input, length)?)?);
+            }
+            _ => bail!("Unknown recording frame tag {}", tag),
+        }
+
+        Ok(())
+    }
+}
+
+fn read_u8(input: &mut impl Read) -> std::io::Result<u8> {
+    let [value] = read_array(input)?;
+    Ok(value)
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> std::io::Result<[u8; N]> {
+    let mut buffer = [0u8; N];
+    input.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn read_bytes(input: &mut impl Read, length: usize) -> std::io::Result<Vec<u8>> {
+    let mut buffer = vec![0u8; length];
+    input.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+// Serves recorded responses in order, keeping the requests the connection produced.
+#[derive(Clone, Default)]
+struct ReplayTransport {
+    exchange: Rc<RefCell<(Option<Response>, Option<Request>)>>,
+}
+
+impl Transport for ReplayTransport {
+    fn exchange(&mut self, request: &Request) -> Result<Response> {
+        let mut exchange = self.exchange.borrow_mut();
+        exchange.1 = Some(request.clone());
+        exchange.0.take().ok_or_else(|| anyhow!("No recorded response for request"))
+    }
+}
+
+/// A loop tick where the replayed drive logic sent a different request to the recorded one.
+pub struct Divergence {
+    pub cycle: usize,
+    pub time: Duration,
+    pub recorded: Vec<u8>,
+    pub replayed: Request,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buffer = [0u8; BUFFER_SIZE];
+        let length = self.replayed.to_wire(&mut buffer).map_err(|_| fmt::Error)?;
+
+        write!(
+            f,
+            "cycle {} at {:?}: recorded {:02x?}, replayed {:02x?} ({:?})",
+            self.cycle,
+            self.time,
+            self.recorded,
+            &buffer[..length],
+            self.replayed.motion_command,
+        )
+    }
+}
+
+pub struct ReplayReport {
+    pub cycles: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+/// Feed a recording back through the drive logic, comparing every request it produces with the
+/// recorded one. Feedback always comes from the recording, so a divergence doesn't compound.
+pub fn replay(recording: &Recording) -> Result<ReplayReport> {
+    let start = Instant::now();
+    let clock = ManualClock::new(start);
+    let transport = ReplayTransport::default();
+    let (mut interface, mut link) = interface();
+    let (core_sender, core_receiver) = mpsc::channel();
+
+    let mut connection = Connection::new(transport.clone(), clock.clone(), recording.settings, core_sender, None);
+
+    let mut buffer = [0u8; BUFFER_SIZE];
+    let mut divergences = Vec::new();
+
+    for (index, cycle) in recording.cycles.iter().enumerate() {
+        // Inputs are consumed after the exchange, so publishing them first is equivalent.
+        if let Some(commands) = &cycle.commands {
+            interface.update_commands(|shared| shared.clone_from(commands));
+        }
+        if cycle.actions != 0 {
+            interface.send_actions(cycle.actions);
+        }
+
+        transport.exchange.borrow_mut().0 = Some(cycle.response.clone());
+        clock.set(start + cycle.time);
+
+        connection.run_cycle(&mut link)?;
+        while core_receiver.try_recv().is_ok() {}
+
+        let replayed = transport.exchange.borrow_mut().1.take().ok_or_else(|| anyhow!("No request replayed"))?;
+        let length = replayed.to_wire(&mut buffer)?;
+        if buffer[..length] != cycle.request[..] {
+            divergences.push(Divergence { cycle: index, time: cycle.time, recorded: cycle.request.clone(), replayed });
+        }
+    }
+
+    Ok(ReplayReport { cycles: recording.cycles.len(), divergences })
+}
+
+pub fn replay_file(path: &Path) -> Result<ReplayReport> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+    replay(&Recording::read(BufReader::new(file))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::simulation::{Harness, SETTINGS};
+    use super::*;
+    use linmot::mci::units::{Acceleration, Position, Velocity};
+    use linmot::mci::{Command, MotionCommand as MciMotionCommand};
+    use puddle::messages::MotionCommand;
+
+    fn command(millimeters: i32) -> MotionCommand {
+        MotionCommand {
+            position: Position::from_millimeters(millimeters),
+            velocity: Velocity::from_meters_per_second(1),
+            acceleration: Acceleration::from_meters_per_second_squared(5),
+            deceleration: Acceleration::from_meters_per_second_squared(5),
+            dwell: 0,
+        }
+    }
+
+    fn record_session() -> Vec<u8> {
+        let (sender, receiver) = mpsc::channel();
+
+        let mut harness = Harness::recorded(vec![command(50), command(150)], Recorder::new(sender));
+        harness.start();
+        harness.run_for(Duration::from_millis(300));
+        harness.set_motion_enabled(false);
+        harness.run_for(Duration::from_millis(100));
+
+        // Dropping the connection ends the recording.
+        drop(harness);
+
+        let mut out = Vec::new();
+        write_recording(&mut out, &SETTINGS, receiver).unwrap();
+        out
+    }
+
+    #[test]
+    fn replaying_a_recording_reproduces_every_request() {
+        let recording = Recording::read(&record_session()[..]).unwrap();
+
+        assert_eq!(recording.settings, SETTINGS);
+        assert!(recording.cycles.len() > 200);
+        assert!(recording.cycles[0].commands.is_some());
+        assert!(recording.cycles.iter().any(|cycle| cycle.actions != 0));
+
+        let report = replay(&recording).unwrap();
+        assert_eq!(report.cycles, recording.cycles.len());
+        assert!(report.divergences.is_empty(), "first divergence: {}", report.divergences[0]);
+    }
+
+    #[test]
+    fn replay_reproduces_timers_that_run_out_during_an_exchange() {
+        let (sender, receiver) = mpsc::channel();
+
+        let dwell = MotionCommand { dwell: 5, ..command(150) };
+        let mut harness = Harness::recorded(vec![command(50), dwell], Recorder::new(sender));
+        harness.start();
+
+        // Exchanges taking different times put the end of each dwell at different points within a tick.
+        for tick in 0..1_000 {
+            harness.drive.model.borrow_mut().latency = Duration::from_micros(if tick % 3 == 0 { 1_500 } else { 0 });
+            harness.tick();
+        }
+        drop(harness);
+
+        let mut out = Vec::new();
+        write_recording(&mut out, &SETTINGS, receiver).unwrap();
+
+        let report = replay(&Recording::read(&out[..]).unwrap()).unwrap();
+        assert!(report.divergences.is_empty(), "first divergence: {}", report.divergences[0]);
+    }
+
+    #[test]
+    fn replay_reports_where_changed_logic_diverges() {
+        let mut recording = Recording::read(&record_session()[..]).unwrap();
+
+        // Holding while paused uses the hard minimum deceleration.
+        let deceleration_min = Acceleration::from_meters_per_second_squared(8);
+        recording.settings.hard_deceleration_min = deceleration_min;
+
+        let report = replay(&recording).unwrap();
+        let first = report.divergences.first().expect("replay should diverge");
+        let Some(MciMotionCommand { command: Command::VaiStop { deceleration }, .. }) = first.replayed.motion_command
+        else {
+            panic!("replayed {:?}", first.replayed.motion_command);
+        };
+        assert_eq!(deceleration, deceleration_min);
+    }
+
+    #[test]
+    fn truncated_recording_keeps_complete_cycles() {
+        let bytes = record_session();
+        let complete = Recording::read(&bytes[..]).unwrap();
+
+        let truncated = Recording::read(&bytes[..bytes.len() - 10]).unwrap();
+        assert_eq!(truncated.cycles.len(), complete.cycles.len() - 1);
+    }
+}
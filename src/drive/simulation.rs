@@ -0,0 +1,394 @@
+// Simulated drive and clock for exercising `Connection` tick-by-tick in unit tests.
+//
+// The simulated drive implements just enough of the LinMot state machine to get from switched off to
+// homed, and integrates a VAI profile for `VaiGoToPos` and `VaiStop`. Responses echo the count of the
+// request's motion command, but report the motion state from before that command has had any effect.
+
+use super::recording::Recorder;
+use super::transport::{Clock, ManualClock, Transport};
+use super::{
+    ACTION_RESET_INDEX, ACTION_RESUME, Connection, DriveCommands, DriveFeedback, DriveInterface, DriveLink,
+    MotionMonitor, ResumeProfile, Settings, SlewRates, interface,
+};
+use crate::CoreEvent;
+use anyhow::Result;
+use linmot::mci::units::{Acceleration, Current, Jerk, Position, Velocity};
+use linmot::mci::{Command, ControlFlags, StatusFlags, WarningFlags};
+use linmot::udp::{Request, Response};
+use puddle::messages::MotionCommand as CoreMotionCommand;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+pub const INTERVAL: Duration = Duration::from_millis(2);
+
+pub const SETTINGS: Settings = Settings {
+    interval: INTERVAL,
+    overshoot_margin: Position::from_millimeters(5),
+    hard_deceleration_min: Acceleration::from_meters_per_second_squared(7),
+    hard_deceleration_max: Acceleration::from_meters_per_second_squared(30),
+    slew_rates: SlewRates { position: Velocity(0), velocity: Acceleration(0), acceleration: Jerk(0) },
+    motion_monitor: MotionMonitor {
+        following_error: Position(0),
+        following_error_time: Duration::ZERO,
+        stall_time: Duration::ZERO,
+    },
+    resume_profile: ResumeProfile { velocity: Velocity(0), acceleration: Acceleration(0), ramp_cycles: 0 },
+};
+
+// Integration step within each exchange.
+const SUBSTEP: Duration = Duration::from_micros(20);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SimState {
+    ReadyToSwitchOn,
+    OperationEnabled,
+    Homing,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub position: f64,
+    pub velocity: f64,
+}
+
+/// Drive model state, in SI units.
+pub struct DriveModel {
+    state: SimState,
+    homed: bool,
+    motion_command_count: u8,
+    command: Command,
+    position: f64,
+    velocity: f64,
+    acceleration: f64,
+    last_exchange: Option<Instant>,
+    // The actual position can't go past this, as if something were in the way.
+    pub obstruction: Option<f64>,
+    // Current drawn while the demand position is past the obstruction, pushing against it.
+    pub obstruction_current: i16,
+    // How long each exchange takes, which the clock moves on by.
+    pub latency: Duration,
+    pub history: Vec<Sample>,
+    pub commands: Vec<Command>,
+}
+
+impl DriveModel {
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+
+    pub fn velocity(&self) -> f64 {
+        self.velocity
+    }
+
+    fn raw_state(&self) -> u16 {
+        match self.state {
+            SimState::ReadyToSwitchOn => 0x0200,
+            SimState::OperationEnabled => {
+                let motion_active = self.velocity != 0.0;
+                let in_target = matches!(self.command, Command::VaiGoToPos { target_position, .. }
+                    if target_position == Position((self.position * 1e7).round() as i32));
+
+                let sub_state = (self.motion_command_count & 0xF)
+                    | (u8::from(motion_active) << 5)
+                    | (u8::from(in_target) << 6)
+                    | (u8::from(self.homed) << 7);
+
+                0x0800 | u16::from(sub_state)
+            }
+            SimState::Homing => 0x090F,
+        }
+    }
+
+    fn response(&self) -> Response {
+        let demand_position = Position((self.position * 1e7).round() as i32);
+        let actual_position = match self.obstruction {
+            Some(obstruction) => Position((self.position.min(obstruction) * 1e7).round() as i32),
+            None => demand_position,
+        };
+        let demand_velocity = (self.velocity * 1e6).round() as i32;
+        let demand_acceleration = (self.acceleration * 1e5).round() as i32;
+
+        Response {
+            status_flags: Some(StatusFlags::empty()),
+            raw_state: Some(self.raw_state()),
+            actual_position: Some(actual_position),
+            demand_position: Some(demand_position),
+            current: Some(Current(match self.obstruction {
+                Some(obstruction) if self.position > obstruction => self.obstruction_current,
+                _ => 0,
+            })),
+            warning_flags: Some(WarningFlags::empty()),
+            raw_error_code: Some(0),
+            monitoring_channel: Some((demand_velocity as u32, demand_acceleration as u32, 300, 80)),
+            realtime_configuration: None,
+        }
+    }
+
+    fn apply(&mut self, request: &Request) {
+        let control_flags = request.control_flags.unwrap_or_default();
+
+        self.state = match self.state {
+            _ if !control_flags.contains(ControlFlags::SWITCH_ON) => SimState::ReadyToSwitchOn,
+            SimState::ReadyToSwitchOn => SimState::OperationEnabled,
+            SimState::OperationEnabled if control_flags.contains(ControlFlags::HOME) && !self.homed => SimState::Homing,
+            SimState::Homing if !control_flags.contains(ControlFlags::HOME) => {
+                self.homed = true;
+                SimState::OperationEnabled
+            }
+            state => state,
+        };
+
+        if self.state != SimState::OperationEnabled {
+            self.command = Command::NoOperation;
+            self.velocity = 0.0;
+            self.acceleration = 0.0;
+            return;
+        }
+
+        if let Some(motion_command) = request.motion_command {
+            if self.homed && motion_command.count != self.motion_command_count {
+                self.motion_command_count = motion_command.count;
+                self.command = motion_command.command;
+                self.commands.push(motion_command.command);
+            }
+        }
+    }
+
+    fn advance(&mut self, duration: Duration) {
+        let start_velocity = self.velocity;
+
+        let mut remaining = duration;
+        while !remaining.is_zero() {
+            let step = remaining.min(SUBSTEP);
+            self.step(step.as_secs_f64());
+            remaining -= step;
+        }
+
+        if !duration.is_zero() {
+            self.acceleration = (self.velocity - start_velocity) / duration.as_secs_f64();
+        }
+
+        self.history.push(Sample { position: self.position, velocity: self.velocity });
+    }
+
+    fn step(&mut self, h: f64) {
+        let (acceleration, velocity_limit) = match self.command {
+            Command::VaiGoToPos { target_position, maximal_velocity, acceleration, deceleration } => {
+                let target = target_position.0 as f64 * 1e-7;
+                let v_max = maximal_velocity.0 as f64 * 1e-6;
+                let acc = acceleration.0 as f64 * 1e-5;
+                let dec = deceleration.0 as f64 * 1e-5;
+
+                let distance = target - self.position;
+                if distance.abs() < 1e-7 && self.velocity.abs() < 1e-4 {
+                    self.position = target;
+                    self.velocity = 0.0;
+                    return;
+                }
+
+                let direction = if distance != 0.0 { distance.signum() } else { -self.velocity.signum() };
+                let v = self.velocity;
+
+                let a = if v * direction < 0.0 {
+                    // Moving away from the target, brake and turn around.
+                    direction * dec
+                } else if dec > 0.0 && v * v / (2.0 * dec) >= distance.abs() {
+                    // Braking onto the target, using exactly what is needed unless that is beyond the
+                    // configured deceleration, in which case the target is overshot.
+                    let needed = v * v / (2.0 * distance.abs().max(1e-9));
+                    -direction * if needed <= dec * 1.05 { needed } else { dec }
+                } else if v.abs() < v_max {
+                    direction * acc
+                } else if v.abs() > v_max {
+                    -direction * dec
+                } else {
+                    0.0
+                };
+
+                (a, v_max)
+            }
+            Command::VaiStop { .. } if self.velocity == 0.0 => (0.0, f64::INFINITY),
+            Command::VaiStop { deceleration } => {
+                let dec = deceleration.0 as f64 * 1e-5;
+                (-self.velocity.signum() * dec, f64::INFINITY)
+            }
+            _ => (0.0, f64::INFINITY),
+        };
+
+        let v0 = self.velocity;
+        let mut v1 = v0 + acceleration * h;
+
+        // Braking never reverses direction within a step, and accelerating never passes the limit.
+        if v0 != 0.0 && v0.signum() != v1.signum() && acceleration.signum() != v0.signum() {
+            v1 = 0.0;
+        }
+        if v1.abs() > velocity_limit && v0.abs() <= velocity_limit {
+            v1 = v1.signum() * velocity_limit;
+        }
+
+        self.position += (v0 + v1) / 2.0 * h;
+        self.velocity = v1;
+    }
+}
+
+/// Simulated drive transport. Clones share the same drive model.
+#[derive(Clone)]
+pub struct SimulatedDrive {
+    pub model: Rc<RefCell<DriveModel>>,
+    clock: ManualClock,
+}
+
+impl SimulatedDrive {
+    pub fn new(clock: ManualClock) -> Self {
+        let model = DriveModel {
+            state: SimState::ReadyToSwitchOn,
+            homed: false,
+            motion_command_count: 0,
+            command: Command::NoOperation,
+            position: 0.0,
+            velocity: 0.0,
+            acceleration: 0.0,
+            last_exchange: None,
+            obstruction: None,
+            obstruction_current: 0,
+            latency: Duration::ZERO,
+            history: Vec::new(),
+            commands: Vec::new(),
+        };
+
+        Self { model: Rc::new(RefCell::new(model)), clock }
+    }
+}
+
+impl Transport for SimulatedDrive {
+    fn exchange(&mut self, request: &Request) -> Result<Response> {
+        let mut model = self.model.borrow_mut();
+
+        let now = self.clock.now();
+        let elapsed = model.last_exchange.map_or(Duration::ZERO, |last| now.duration_since(last));
+        model.last_exchange = Some(now);
+        model.advance(elapsed);
+        model.apply(request);
+        self.clock.set(now + model.latency);
+
+        Ok(model.response())
+    }
+}
+
+/// A connection wired to a simulated drive, driven one cycle at a time.
+pub struct Harness {
+    pub connection: Connection<SimulatedDrive, ManualClock>,
+    pub link: DriveLink,
+    pub interface: DriveInterface,
+    pub drive: SimulatedDrive,
+    pub feedback: DriveFeedback,
+    core_receiver: mpsc::Receiver<CoreEvent>,
+}
+
+impl Harness {
+    pub fn new(commands: Vec<CoreMotionCommand>) -> Self {
+        Self::build(commands, SETTINGS, None)
+    }
+
+    /// As `new`, but with different drive loop settings.
+    pub fn with_settings(commands: Vec<CoreMotionCommand>, settings: Settings) -> Self {
+        Self::build(commands, settings, None)
+    }
+
+    /// As `new`, but with every exchange and input recorded from the first tick.
+    pub fn recorded(commands: Vec<CoreMotionCommand>, recorder: Recorder) -> Self {
+        Self::build(commands, SETTINGS, Some(recorder))
+    }
+
+    fn build(commands: Vec<CoreMotionCommand>, settings: Settings, recorder: Option<Recorder>) -> Self {
+        let clock = ManualClock::new(Instant::now());
+        let drive = SimulatedDrive::new(clock.clone());
+        let (interface, link) = interface();
+        let (core_sender, core_receiver) = mpsc::channel();
+
+        let mut connection = Connection::new(drive.clone(), clock, settings, core_sender, None);
+        if let Some(recorder) = recorder {
+            connection.record_to(recorder);
+        }
+
+        let mut harness =
+            Self { connection, link, interface, drive, feedback: DriveFeedback::default(), core_receiver };
+
+        harness.update_commands(|shared| {
+            shared.power_enabled = true;
+            shared.commands = commands;
+        });
+
+        assert!(
+            harness
+                .run_until(Duration::from_secs(1), |h| h.feedback.drive_state == puddle::messages::DriveState::Paused),
+            "simulated drive did not become ready",
+        );
+
+        harness
+    }
+
+    pub fn update_commands(&mut self, update: impl FnOnce(&mut DriveCommands)) {
+        self.interface.update_commands(update);
+    }
+
+    pub fn start(&mut self) {
+        self.update_commands(|shared| shared.motion_enabled = true);
+        self.interface.send_actions(ACTION_RESET_INDEX);
+    }
+
+    pub fn resume(&mut self) {
+        self.interface.send_actions(ACTION_RESUME);
+        self.update_commands(|shared| shared.motion_enabled = true);
+    }
+
+    pub fn set_motion_enabled(&mut self, enabled: bool) {
+        self.update_commands(|shared| shared.motion_enabled = enabled);
+    }
+
+    pub fn tick(&mut self) {
+        self.connection.run_cycle(&mut self.link).unwrap();
+
+        while let Ok(event) = self.core_receiver.try_recv() {
+            if let CoreEvent::DriveStateUpdated(feedback) = event {
+                self.feedback = feedback;
+            }
+        }
+    }
+
+    pub fn run_for(&mut self, duration: Duration) {
+        for _ in 0..duration.div_duration_f64(INTERVAL).ceil() as u64 {
+            self.tick();
+        }
+    }
+
+    /// Tick until the predicate holds, returning false if it did not within the timeout.
+    pub fn run_until(&mut self, timeout: Duration, mut predicate: impl FnMut(&Self) -> bool) -> bool {
+        for _ in 0..timeout.div_duration_f64(INTERVAL).ceil() as u64 {
+            self.tick();
+            if predicate(self) {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn position(&self) -> f64 {
+        self.drive.model.borrow().position()
+    }
+
+    pub fn velocity(&self) -> f64 {
+        self.drive.model.borrow().velocity()
+    }
+
+    /// Samples recorded from now on can be inspected with `history_since`.
+    pub fn history_len(&self) -> usize {
+        self.drive.model.borrow().history.len()
+    }
+
+    pub fn history_since(&self, start: usize) -> Vec<Sample> {
+        self.drive.model.borrow().history[start..].to_vec()
+    }
+}
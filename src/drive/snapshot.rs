@@ -0,0 +1,154 @@
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// Single-producer single-consumer snapshot exchange, implemented as a triple buffer.
+//
+// The writer and reader each own one of the three slots outright, and the third ("middle") slot is
+// exchanged between them with a single atomic swap. Neither side ever waits for the other, and the
+// reader always observes the most recently published value. The reader's own slot is stable until
+// it polls again, so it can be borrowed without copying.
+
+const INDEX_MASK: u8 = 0b011;
+const FRESH: u8 = 0b100;
+
+struct Slot<T> {
+    generation: u64,
+    value: T,
+}
+
+struct Shared<T> {
+    slots: [UnsafeCell<Slot<T>>; 3],
+    // Index of the middle slot, with FRESH set if it was published and not yet taken by the reader.
+    middle: AtomicU8,
+}
+
+// Each slot is only ever accessed by whichever side currently owns its index, and ownership is
+// transferred with acquire/release ordering on `middle`. The reader hands out shared borrows of its
+// slot, so the values must be safe to share between threads as well as to send.
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+/// Publishing half, held by the core thread.
+pub struct Writer<T> {
+    shared: Arc<Shared<T>>,
+    index: u8,
+    generation: u64,
+}
+
+/// Receiving half, held by the drive thread.
+pub struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    index: u8,
+    generation: u64,
+}
+
+pub fn channel<T: Clone>(initial: T) -> (Writer<T>, Reader<T>) {
+    let slot = || UnsafeCell::new(Slot { generation: 0, value: initial.clone() });
+
+    let shared = Arc::new(Shared { slots: [slot(), slot(), slot()], middle: AtomicU8::new(1) });
+
+    (Writer { shared: shared.clone(), index: 0, generation: 0 }, Reader { shared, index: 2, generation: 0 })
+}
+
+impl<T: Clone> Writer<T> {
+    /// Publish a new snapshot, replacing any the reader has not yet taken. Never blocks.
+    pub fn publish(&mut self, value: &T) {
+        self.generation += 1;
+
+        // SAFETY: The writer exclusively owns the slot at `self.index` until it is swapped out below.
+        let slot = unsafe { &mut *self.shared.slots[self.index as usize].get() };
+        slot.value.clone_from(value);
+        slot.generation = self.generation;
+
+        let previous = self.shared.middle.swap(self.index | FRESH, Ordering::AcqRel);
+        self.index = previous & INDEX_MASK;
+    }
+}
+
+impl<T> Reader<T> {
+    /// Take the most recently published snapshot, if there has been one since the last call. Never blocks.
+    pub fn poll(&mut self) -> Option<&T> {
+        if self.shared.middle.load(Ordering::Relaxed) & FRESH == 0 {
+            return None;
+        }
+
+        let previous = self.shared.middle.swap(self.index, Ordering::AcqRel);
+        self.index = previous & INDEX_MASK;
+
+        self.generation = self.slot().generation;
+
+        Some(self.latest())
+    }
+
+    /// The snapshot returned by the last successful `poll`, or the initial value.
+    pub fn latest(&self) -> &T {
+        &self.slot().value
+    }
+
+    /// Number of snapshots published before the one returned by `latest`.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn slot(&self) -> &Slot<T> {
+        // SAFETY: The reader exclusively owns the slot at `self.index` until it is swapped out in `poll`.
+        unsafe { &*self.shared.slots[self.index as usize].get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_to_poll_before_first_publish() {
+        let (_writer, mut reader) = channel(7u32);
+
+        assert_eq!(reader.poll(), None);
+        assert_eq!(*reader.latest(), 7);
+        assert_eq!(reader.generation(), 0);
+    }
+
+    #[test]
+    fn poll_returns_only_the_latest_snapshot_once() {
+        let (mut writer, mut reader) = channel(0u32);
+
+        writer.publish(&1);
+        writer.publish(&2);
+        writer.publish(&3);
+
+        assert_eq!(reader.poll(), Some(&3));
+        assert_eq!(reader.generation(), 3);
+        assert_eq!(reader.poll(), None);
+        assert_eq!(*reader.latest(), 3);
+
+        writer.publish(&4);
+        assert_eq!(reader.poll(), Some(&4));
+        assert_eq!(reader.generation(), 4);
+    }
+
+    #[test]
+    fn reader_never_goes_backwards_across_threads() {
+        let (mut writer, mut reader) = channel(vec![0u64; 16]);
+
+        let handle = std::thread::spawn(move || {
+            for i in 1..=100_000u64 {
+                writer.publish(&vec![i; 16]);
+            }
+        });
+
+        let mut last = 0;
+        while last < 100_000 {
+            if reader.poll().is_some() {
+                let value = reader.latest();
+                // A torn snapshot would mix values from different publishes.
+                assert!(value.iter().all(|&v| v == value[0]));
+                assert!(value[0] > last);
+                assert_eq!(reader.generation(), value[0]);
+                last = value[0];
+            }
+        }
+
+        handle.join().unwrap();
+    }
+}
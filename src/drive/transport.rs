@@ -0,0 +1,101 @@
+use anyhow::Result;
+use linmot::udp::{BUFFER_SIZE, CONTROLLER_PORT, DRIVE_PORT, Request, Response};
+use log::{info, trace};
+use std::cell::Cell;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Request/response exchange with a drive.
+pub trait Transport {
+    /// Send a request and wait for the matching response.
+    fn exchange(&mut self, request: &Request) -> Result<Response>;
+}
+
+/// Source of time for the drive loop.
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+/// LinUDP transport to a real drive.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    buffer: [u8; BUFFER_SIZE],
+}
+
+impl UdpTransport {
+    pub fn connect(address: &str, timeout: Duration) -> Result<Self> {
+        info!("Connecting to drive at {}:{}...", address, DRIVE_PORT);
+
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, CONTROLLER_PORT))?;
+
+        socket.connect((address, DRIVE_PORT))?;
+        socket.set_read_timeout(Some(timeout))?;
+
+        let mut transport = Self { socket, buffer: [0u8; BUFFER_SIZE] };
+
+        // Send a packet to check the drive is responding.
+        transport.exchange(&Request::default())?;
+
+        info!("Connected to drive at {:?} from {:?}", transport.socket.peer_addr()?, transport.socket.local_addr()?);
+
+        Ok(transport)
+    }
+}
+
+impl Transport for UdpTransport {
+    fn exchange(&mut self, request: &Request) -> Result<Response> {
+        trace!("Sending request: {:?}", request);
+
+        let to_send = request.to_wire(&mut self.buffer)?;
+        self.socket.send(&self.buffer[..to_send])?;
+
+        let received = self.socket.recv(&mut self.buffer)?;
+        let response = Response::from_wire(&self.buffer[..received])?;
+
+        trace!("Received response: {:?}", response);
+
+        Ok(response)
+    }
+}
+
+/// Wall-clock time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        // Use sleep_until when it is stabilized.
+        std::thread::sleep(duration);
+    }
+}
+
+/// Manually advanced clock, for driving a connection outside real time.
+#[derive(Clone)]
+pub struct ManualClock {
+    now: Rc<Cell<Instant>>,
+}
+
+impl ManualClock {
+    pub fn new(now: Instant) -> Self {
+        Self { now: Rc::new(Cell::new(now)) }
+    }
+
+    pub fn set(&self, now: Instant) {
+        self.now.set(now);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
@@ -5,7 +5,8 @@ use crate::hid::messages::{
 use anyhow::Result;
 use log::trace;
 use puddle::messages::{
-    ClientMessage, CommandUpdate, CoreMessage, DriveState, MotionAction, MotionCommand, MotionCommandFields,
+    ClientMessage, CommandUnits, CommandUpdate, CoreMessage, DriveState, MotionAction, MotionCommand,
+    MotionCommandFields, SpeedOverride,
 };
 use puddle::units::{Acceleration, Position, Velocity};
 use puddle::{ControllerId, CoreState, SystemLimits};
@@ -20,6 +21,9 @@ pub struct UiManager {
     motion_commands: [MotionCommand; 2],
     last_sent_variables: HashMap<u8, VariableEntry>,
     holding_start: bool,
+    // While held, the speed and accel. encoders adjust the global override instead of the commands.
+    holding_speed: bool,
+    holding_accel: bool,
 }
 
 const DEFAULT_COMMANDS: [MotionCommand; 2] = [
@@ -28,12 +32,14 @@ const DEFAULT_COMMANDS: [MotionCommand; 2] = [
         velocity: Velocity::from_meters_per_second(1),
         acceleration: Acceleration::from_millimeters_per_second_squared(500),
         deceleration: Acceleration::from_millimeters_per_second_squared(500),
+        dwell: 0,
     },
     MotionCommand {
         position: Position::ZERO,
         velocity: Velocity::from_meters_per_second(1),
         acceleration: Acceleration::from_millimeters_per_second_squared(500),
         deceleration: Acceleration::from_millimeters_per_second_squared(500),
+        dwell: 0,
     },
 ];
 
@@ -51,9 +57,9 @@ impl UiManager {
                 top_margin: 0,
                 lines: vec![
                     c"Stroke".to_owned(),
-                    c"{0} to {1}".to_owned(), // Start, End position
-                    c"Speed  {2}".to_owned(), // Velocity
-                    c"Accel. {3}".to_owned(), // Acceleration
+                    c"{0} to {1}".to_owned(),     // Start, End position
+                    c"Spd. {2} {12}%".to_owned(), // Velocity, override
+                    c"Acc. {3} {13}%".to_owned(), // Acceleration, override
                 ],
             },
             left_animation_type: ScreenAnimation::MoveTop,
@@ -64,7 +70,7 @@ impl UiManager {
                 lines: vec![
                     c"{4}".to_owned(),       // Current state OR error
                     c"{5} / {6}".to_owned(), // Actual, Demand position
-                    c"{7}".to_owned(),       // Current OR warnings
+                    c"{7}".to_owned(),       // Current OR warnings OR time until too hot
                 ],
             },
             right_animation_type: ScreenAnimation::MoveTop,
@@ -78,6 +84,8 @@ impl UiManager {
             motion_commands: DEFAULT_COMMANDS,
             last_sent_variables: HashMap::new(),
             holding_start: false,
+            holding_speed: false,
+            holding_accel: false,
         }
     }
 
@@ -119,10 +127,15 @@ impl UiManager {
                     set: None,
                     base_version: None,
                     commands: self.motion_commands.to_vec(),
+                    caps: None,
+                    units: CommandUnits::Absolute,
                 },
             ]);
         }
 
+        // Keep edits within the active set's caps as well, so they aren't rejected.
+        let system_limits = &core_state.command_set_caps.narrow(system_limits);
+
         let mut messages = Vec::new();
         let mut variables = Vec::new();
 
@@ -133,7 +146,25 @@ impl UiManager {
 
             // TODO: The delta acceleration handling doesn't feel good at the HID tick rate, these values came from the LVGL tick
             let delta = delta as i32;
+            let adjust_override = |percent: u16| -> u16 {
+                (i32::from(percent) + delta * delta.abs()).clamp(1, i32::from(SpeedOverride::MAX_PERCENT)) as u16
+            };
+
             match i {
+                2 if self.holding_speed => {
+                    let velocity = adjust_override(core_state.speed_override.velocity);
+                    messages.push(ClientMessage::SetSpeedOverride {
+                        seq: 0,
+                        speed_override: SpeedOverride { velocity, ..core_state.speed_override },
+                    });
+                }
+                3 if self.holding_accel => {
+                    let acceleration = adjust_override(core_state.speed_override.acceleration);
+                    messages.push(ClientMessage::SetSpeedOverride {
+                        seq: 0,
+                        speed_override: SpeedOverride { acceleration, ..core_state.speed_override },
+                    });
+                }
                 0 => {
                     self.motion_commands[0].position.0 = self.motion_commands[0]
                         .position
@@ -150,6 +181,7 @@ impl UiManager {
                                 velocity: None,
                                 acceleration: None,
                                 deceleration: None,
+                                dwell: None,
                             },
                         },
                     });
@@ -174,6 +206,7 @@ impl UiManager {
                                     velocity: None,
                                     acceleration: None,
                                     deceleration: None,
+                                    dwell: None,
                                 },
                             },
                         });
@@ -195,6 +228,7 @@ impl UiManager {
                                 velocity: None,
                                 acceleration: None,
                                 deceleration: None,
+                                dwell: None,
                             },
                         },
                     });
@@ -219,6 +253,7 @@ impl UiManager {
                                     velocity: None,
                                     acceleration: None,
                                     deceleration: None,
+                                    dwell: None,
                                 },
                             },
                         });
@@ -241,6 +276,7 @@ impl UiManager {
                                 velocity: Some(self.motion_commands[0].velocity),
                                 acceleration: None,
                                 deceleration: None,
+                                dwell: None,
                             },
                         },
                     });
@@ -254,6 +290,7 @@ impl UiManager {
                                 velocity: Some(self.motion_commands[1].velocity),
                                 acceleration: None,
                                 deceleration: None,
+                                dwell: None,
                             },
                         },
                     });
@@ -278,6 +315,7 @@ impl UiManager {
                                 velocity: None,
                                 acceleration: Some(self.motion_commands[0].acceleration),
                                 deceleration: Some(self.motion_commands[0].deceleration),
+                                dwell: None,
                             },
                         },
                     });
@@ -291,6 +329,7 @@ impl UiManager {
                                 velocity: None,
                                 acceleration: Some(self.motion_commands[1].acceleration),
                                 deceleration: Some(self.motion_commands[1].deceleration),
+                                dwell: None,
                             },
                         },
                     });
@@ -305,7 +344,7 @@ impl UiManager {
                     if encoder_id == 0 {
                         match core_state.drive_state {
                             DriveState::Off => messages.push(ClientMessage::SetDrivePower { seq: 0, enabled: true }),
-                            DriveState::Preparing | DriveState::Paused | DriveState::Moving => {
+                            DriveState::Preparing | DriveState::Paused | DriveState::Moving | DriveState::Manual => {
                                 messages.push(ClientMessage::SetDrivePower { seq: 0, enabled: false })
                             }
                             DriveState::Errored => messages.push(ClientMessage::AcknowledgeError { seq: 0 }),
@@ -331,17 +370,27 @@ impl UiManager {
                             set: None,
                             base_version: None,
                             commands: self.motion_commands.to_vec(),
+                            caps: None,
+                            units: CommandUnits::Absolute,
                         });
                     }
                 }
                 HidInputEvent::ButtonHoldStart { encoder_id } => {
                     if encoder_id == 0 {
                         self.holding_start = true;
+                    } else if encoder_id == 2 {
+                        self.holding_speed = true;
+                    } else if encoder_id == 3 {
+                        self.holding_accel = true;
                     }
                 }
                 HidInputEvent::ButtonHoldEnd { encoder_id } => {
                     if encoder_id == 0 {
                         self.holding_start = false;
+                    } else if encoder_id == 2 {
+                        self.holding_speed = false;
+                    } else if encoder_id == 3 {
+                        self.holding_accel = false;
                     }
                 }
                 _ => {}
@@ -391,11 +440,13 @@ impl UiManager {
             // 4 = Error OR Current State
             // 5 = Actual Position
             // 6 = Demand Position
-            // 7 = Warnings OR Motor Current
+            // 7 = Warnings OR Time Until Too Hot OR Motor Current
             // 8 = Enable / Disable / Acknowledge
             // 9 = Resume / Pause
             // 10 = Start / ""
             // 11 = End / Stroke
+            // 12 = Velocity Override
+            // 13 = Acceleration Override
             variables.push(VariableEntry::FixedPoint {
                 index: 0,
                 decimals: 0,
@@ -439,6 +490,11 @@ impl UiManager {
                     index: 7,
                     value: CString::new(core_state.warnings.join(", ")).unwrap(),
                 });
+            } else if let Some(seconds) = core_state.motor_time_to_limit {
+                variables.push(VariableEntry::ShortString {
+                    index: 7,
+                    value: CString::new(format!("Hot in {}:{:02}", seconds / 60, seconds % 60)).unwrap(),
+                });
             } else {
                 variables.push(VariableEntry::FixedPoint {
                     index: 7,
@@ -450,7 +506,9 @@ impl UiManager {
                 index: 8,
                 value: match &core_state.drive_state {
                     DriveState::Off => c"Enable".to_owned(),
-                    DriveState::Preparing | DriveState::Paused | DriveState::Moving => c"Disable".to_owned(),
+                    DriveState::Preparing | DriveState::Paused | DriveState::Moving | DriveState::Manual => {
+                        c"Disable".to_owned()
+                    }
                     DriveState::Errored => c"Ack.".to_owned(),
                     _ => c"".to_owned(),
                 },
@@ -471,6 +529,16 @@ impl UiManager {
                 index: 11,
                 value: if !self.holding_start { c"End".to_owned() } else { c"Stroke".to_owned() },
             });
+            variables.push(VariableEntry::FixedPoint {
+                index: 12,
+                decimals: 0,
+                value: core_state.speed_override.velocity as i16,
+            });
+            variables.push(VariableEntry::FixedPoint {
+                index: 13,
+                decimals: 0,
+                value: core_state.speed_override.acceleration as i16,
+            });
 
             // Filter to only send the ones that have changed since the last time we sent a message
             variables.retain(|v| {
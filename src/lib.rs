@@ -1,4 +1,8 @@
-use crate::messages::DriveState;
+use crate::messages::{
+    CommandSetCaps, CurrentLimitEvent, CurrentLimits, DriveState, GuardViolation, MotionCommand, MotionFault, Pattern,
+    Playlist, PlaylistProgress, PositionGuards, Repetition, SoftRamp, SpeedOverride, ThermalStatus, TransitionMode,
+    Variation, WorkCoordinates,
+};
 use mio::Token;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
@@ -95,5 +99,27 @@ pub struct CoreState {
     pub warnings: Vec<String>,
     pub error_code: Option<String>,
     pub command_set_version: u64,
+    pub command_set_caps: CommandSetCaps,
     pub write_access_holder: Option<ControllerId>,
+    pub repetition: Repetition,
+    pub remaining_repetitions: Option<u32>,
+    pub speed_override: SpeedOverride,
+    pub cycle_frequency: Option<u32>,
+    pub max_cycle_frequency: Option<u32>,
+    pub soft_ramp: SoftRamp,
+    pub transition_mode: TransitionMode,
+    pub pattern: Option<Pattern>,
+    pub variation: Option<Variation>,
+    pub playlist: Playlist,
+    pub playlist_progress: Option<PlaylistProgress>,
+    pub amplitude: u16,
+    pub effective_command: Option<MotionCommand>,
+    pub work_coordinates: WorkCoordinates,
+    pub position_guards: PositionGuards,
+    pub guard_violation: Option<GuardViolation>,
+    pub motion_fault: Option<MotionFault>,
+    pub current_limits: CurrentLimits,
+    pub current_limit_event: Option<CurrentLimitEvent>,
+    pub thermal: ThermalStatus,
+    pub motor_time_to_limit: Option<u32>,
 }
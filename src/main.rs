@@ -1,21 +1,36 @@
-use crate::drive::{ACTION_ACK_ERROR, ACTION_RESET_INDEX, DriveFeedback};
+use crate::drive::{
+    ACTION_ACK_ERROR, ACTION_CROSSFADE, ACTION_RESET_INDEX, ACTION_RESUME, DriveFeedback, ManualMotion,
+};
 use crate::hid::messages::InputReport;
+use crate::profile::CycleTiming;
 use anyhow::{Context, Result, anyhow};
 use clap::Parser;
 use linmot::mci::ErrorCode;
-use log::{trace, warn};
+use log::{info, trace, warn};
 use puddle::messages::{
-    AckFailureReason, ClientMessage, CoreMessage, DriveState, MotionAction, MotionCommand, SavedSetMetadata,
+    AckFailureReason, ClientMessage, CommandIssue, CommandSetCaps, CommandUnits, CoreMessage, CurrentLimitAction,
+    CurrentLimitEvent, DriveState, EndAction, GuardViolation, LegTiming, MotionAction, MotionCommand, PlaylistAction,
+    PlaylistLength, PlaylistProgress, PositionGuards, SavedSetMetadata, SpeedOverride, TransitionMode, Variation,
+    WorkCoordinates,
 };
-use puddle::units::{Acceleration, Position, Velocity};
+use puddle::units::{Acceleration, Current, DriveTemperature, Jerk, MotorTemperature, Position, Velocity};
 use puddle::{ControllerId, CoreState, SystemLimits};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::mpsc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 mod drive;
 mod hid;
 mod metrics;
+mod patterns;
+mod playlist;
+mod profile;
+mod relative;
+mod thermal;
+mod trajectory;
+mod validation;
+mod variation;
 mod websocket;
 
 fn from_hex(s: &str) -> Result<u16> {
@@ -26,7 +41,8 @@ fn from_hex(s: &str) -> Result<u16> {
 #[command(version, about, long_about = None)]
 struct Options {
     /// Drive controller hostname or IP address
-    drive_address: String,
+    #[clap(required_unless_present = "replay")]
+    drive_address: Option<String>,
     /// Connect to USB remote controller
     #[clap(short = 'u', long)]
     enable_usb: bool,
@@ -54,6 +70,60 @@ struct Options {
     /// Position overshoot limit in millimeters
     #[clap(long, default_value = "5.0")]
     position_overshoot_limit: f64,
+    /// Maximum rate of change for edited positions while moving, in millimeters per second (0 for no limit)
+    #[clap(long, default_value = "250.0")]
+    position_slew_rate: f64,
+    /// Maximum rate of change for edited velocities while moving, in meters per second squared (0 for no limit)
+    #[clap(long, default_value = "5.0")]
+    velocity_slew_rate: f64,
+    /// Maximum rate of change for edited accelerations while moving, in meters per second cubed (0 for no limit)
+    #[clap(long, default_value = "100.0")]
+    acceleration_slew_rate: f64,
+    /// Stop motion if the actual position lags the demand by more than this many millimeters (0 to disable)
+    #[clap(long, default_value = "3.0")]
+    following_error_limit: f64,
+    /// How long the following error may be over the limit before stopping, in milliseconds
+    #[clap(long, default_value = "50")]
+    following_error_time: u64,
+    /// Stop motion if the actual position stops moving for this many milliseconds while it should be (0 to disable)
+    #[clap(long, default_value = "100")]
+    stall_time: u64,
+    /// Velocity limit in meters per second for approaching the interrupted target when motion resumes (0 to disable)
+    #[clap(long, default_value = "0.1")]
+    resume_velocity: f64,
+    /// Acceleration limit in meters per second squared for approaching the interrupted target when motion resumes
+    #[clap(long, default_value = "1.0")]
+    resume_acceleration: f64,
+    /// Passes through the command set to ramp back up to the full command velocity and acceleration after resuming
+    #[clap(long, default_value = "2")]
+    resume_ramp_cycles: u32,
+    /// Drive temperature in degrees C above which velocity and acceleration are derated
+    #[clap(long, default_value = "60.0")]
+    drive_derate_temperature: f64,
+    /// Drive temperature in degrees C at which motion is paused to cool down
+    #[clap(long, default_value = "70.0")]
+    drive_pause_temperature: f64,
+    /// Drive temperature in degrees C below which motion may resume after a thermal pause
+    #[clap(long, default_value = "55.0")]
+    drive_resume_temperature: f64,
+    /// Motor temperature in degrees C above which velocity and acceleration are derated
+    #[clap(long, default_value = "70.0")]
+    motor_derate_temperature: f64,
+    /// Motor temperature in degrees C at which motion is paused to cool down
+    #[clap(long, default_value = "90.0")]
+    motor_pause_temperature: f64,
+    /// Motor temperature in degrees C below which motion may resume after a thermal pause
+    #[clap(long, default_value = "60.0")]
+    motor_resume_temperature: f64,
+    /// Velocity and acceleration percentage to derate to by the pause temperature
+    #[clap(long, default_value = "50", value_parser = clap::value_parser!(u16).range(1..=100))]
+    minimum_derating: u16,
+    /// Motor thermal time constant in seconds, for predicting how long until the motor pause temperature
+    #[clap(long, default_value = "900")]
+    motor_time_constant: u64,
+    /// Resume motion automatically once cooled down after a thermal pause
+    #[clap(long)]
+    thermal_auto_resume: bool,
     /// Drive loop interval in milliseconds
     #[clap(short, long, default_value = "2")]
     loop_interval: u64,
@@ -66,6 +136,12 @@ struct Options {
     /// Metrics buffer time in seconds
     #[clap(short = 's', long, default_value = "5")]
     stats_interval: u64,
+    /// Record drive sessions into this directory, one file per connection
+    #[clap(long, value_name = "DIR")]
+    record_drive: Option<PathBuf>,
+    /// Replay a drive session recording through the drive logic, report any differences, and exit
+    #[clap(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
@@ -73,6 +149,23 @@ fn main() -> Result<()> {
 
     let options = Options::parse();
 
+    if let Some(path) = &options.replay {
+        let report = drive::replay_file(path)?;
+
+        info!("Replayed {} drive cycles, {} diverged from the recording", report.cycles, report.divergences.len());
+        for divergence in &report.divergences {
+            warn!("Divergence in {}", divergence);
+        }
+
+        if !report.divergences.is_empty() {
+            return Err(anyhow!("Replay diverged from the recording"));
+        }
+
+        return Ok(());
+    }
+
+    let drive_address = options.drive_address.context("No drive address")?;
+
     let (core_sender, core_receiver) = mpsc::channel();
 
     let hi_io_manager = if options.enable_usb {
@@ -98,6 +191,26 @@ fn main() -> Result<()> {
         deceleration: Acceleration::from_meters_per_second_squared_f64(options.acceleration_limit),
     };
 
+    let thermal_limits = thermal::ThermalLimits {
+        drive: thermal::Thresholds {
+            derate: DriveTemperature::from_celsius_f64(options.drive_derate_temperature),
+            pause: DriveTemperature::from_celsius_f64(options.drive_pause_temperature),
+            resume: DriveTemperature::from_celsius_f64(options.drive_resume_temperature),
+        },
+        motor: thermal::Thresholds {
+            derate: MotorTemperature::from_celsius_f64(options.motor_derate_temperature),
+            pause: MotorTemperature::from_celsius_f64(options.motor_pause_temperature),
+            resume: MotorTemperature::from_celsius_f64(options.motor_resume_temperature),
+        },
+        minimum_derating: options.minimum_derating,
+        auto_resume: options.thermal_auto_resume,
+    };
+    if !thermal_limits.is_valid() {
+        return Err(anyhow!(
+            "Thermal thresholds out of range, or not pausing above the derate and resume temperatures"
+        ));
+    }
+
     let metrics = match metrics::MetricSender::new(
         options.stats_table,
         options.stats_limit,
@@ -110,17 +223,40 @@ fn main() -> Result<()> {
         }
     };
 
+    let drive_settings = drive::Settings {
+        interval: Duration::from_millis(options.loop_interval),
+        overshoot_margin: Position::from_millimeters_f64(options.position_overshoot_limit),
+        hard_deceleration_min: Acceleration::from_meters_per_second_squared_f64(options.acceleration_limit / 2.0),
+        hard_deceleration_max: Acceleration::from_meters_per_second_squared_f64(options.acceleration_limit * 2.0),
+        slew_rates: drive::SlewRates {
+            position: Velocity::from_millimeters_per_second_f64(options.position_slew_rate),
+            velocity: Acceleration::from_meters_per_second_squared_f64(options.velocity_slew_rate),
+            acceleration: Jerk::from_meters_per_second_cubed_f64(options.acceleration_slew_rate),
+        },
+        motion_monitor: drive::MotionMonitor {
+            following_error: Position::from_millimeters_f64(options.following_error_limit),
+            following_error_time: Duration::from_millis(options.following_error_time),
+            stall_time: Duration::from_millis(options.stall_time),
+        },
+        resume_profile: drive::ResumeProfile {
+            velocity: Velocity::from_meters_per_second_f64(options.resume_velocity),
+            acceleration: Acceleration::from_meters_per_second_squared_f64(options.resume_acceleration),
+            ramp_cycles: options.resume_ramp_cycles,
+        },
+    };
+
     let drive = drive::ConnectionManager::new(
-        options.drive_address,
-        Duration::from_millis(options.loop_interval),
-        Position::from_millimeters_f64(options.position_overshoot_limit),
-        Acceleration::from_meters_per_second_squared_f64(options.acceleration_limit / 2.0),
-        Acceleration::from_meters_per_second_squared_f64(options.acceleration_limit * 2.0),
+        drive_address,
+        drive_settings,
+        options.record_drive,
         core_sender.clone(),
         metrics.map(|m| m.sender.clone()),
     );
 
-    let mut core_manager = CoreManager::new(limits, drive, hi_io_manager, websocket_server);
+    let motor_model = thermal::MotorModel::new(Duration::from_secs(options.motor_time_constant));
+
+    let mut core_manager =
+        CoreManager::new(limits, drive_settings, thermal_limits, motor_model, drive, hi_io_manager, websocket_server);
 
     loop {
         let message = match core_receiver.recv() {
@@ -151,29 +287,57 @@ enum CoreEvent {
 
 struct CoreManager {
     limits: SystemLimits,
+    // For working out how the drive will run a command set.
+    drive_settings: drive::Settings,
+    thermal_limits: thermal::ThermalLimits,
+    motor_model: thermal::MotorModel,
     drive: drive::ConnectionManager,
     hid_ui: Option<hid::UiManager>,
     websocket_server: Option<websocket::Server>,
     core_state: CoreState,
+    // Count of current limit events last reported by the drive.
+    current_limit_events: u32,
+    // Motion was paused for cooling, and should resume once cooled down.
+    thermal_resume: bool,
     active_command_set: (u64, Vec<MotionCommand>),
+    playlist_tracker: playlist::Tracker,
+    variation_passes: variation::Passes,
     // TODO: This will be database-backed in the future
-    saved_command_sets: HashMap<String, (u64, Vec<MotionCommand>)>,
+    saved_command_sets: HashMap<String, SavedSet>,
+}
+
+// A saved command set, kept as it was written until it is loaded.
+struct SavedSet {
+    version: u64,
+    commands: Vec<MotionCommand>,
+    caps: CommandSetCaps,
+    units: CommandUnits,
 }
 
 impl CoreManager {
     fn new(
         limits: SystemLimits,
+        drive_settings: drive::Settings,
+        thermal_limits: thermal::ThermalLimits,
+        motor_model: thermal::MotorModel,
         drive: drive::ConnectionManager,
         hid_io: Option<hid::IoManager>,
         websocket_server: Option<websocket::Server>,
     ) -> Self {
         Self {
             limits,
+            drive_settings,
+            thermal_limits,
+            motor_model,
             drive,
             hid_ui: hid_io.map(|io| hid::UiManager::new(io)),
             websocket_server,
             core_state: CoreState::default(),
+            current_limit_events: 0,
+            thermal_resume: false,
             active_command_set: (0, Vec::new()),
+            playlist_tracker: playlist::Tracker::default(),
+            variation_passes: variation::Passes::default(),
             saved_command_sets: HashMap::new(),
         }
     }
@@ -220,11 +384,11 @@ impl CoreManager {
                 if self.core_state.write_access_holder == Some(controller_id) {
                     self.core_state.write_access_holder = None;
 
-                    {
-                        let mut commands = self.drive.interface.commands.lock().unwrap();
+                    self.drive.interface.update_commands(|commands| {
                         commands.power_enabled = false;
                         commands.motion_enabled = false;
-                    }
+                        commands.manual = None;
+                    });
 
                     self.send(
                         None,
@@ -239,19 +403,93 @@ impl CoreManager {
             CoreEvent::DriveStateUpdated(feedback) => {
                 self.core_state.drive_state = feedback.drive_state;
                 self.core_state.active_command_index = feedback.active_command_index;
-                self.core_state.actual_position = feedback.actual_position;
-                self.core_state.demand_position = feedback.demand_position;
-                self.core_state.demand_velocity = feedback.demand_velocity;
-                self.core_state.demand_acceleration = feedback.demand_acceleration;
-                self.core_state.current_draw = feedback.current_draw;
+                let coordinates = self.core_state.work_coordinates;
+                self.core_state.actual_position = coordinates.from_drive(feedback.actual_position);
+                self.core_state.demand_position = coordinates.from_drive(feedback.demand_position);
+                self.core_state.demand_velocity = Velocity(feedback.demand_velocity.0 * coordinates.direction());
+                self.core_state.demand_acceleration =
+                    Acceleration(feedback.demand_acceleration.0 * coordinates.direction());
+                self.core_state.current_draw = Current(feedback.current_draw.0 * coordinates.direction() as i16);
                 self.core_state.drive_temperature = feedback.drive_temperature;
                 self.core_state.motor_temperature = feedback.motor_temperature;
+                self.core_state.remaining_repetitions = feedback.remaining_repetitions;
+                self.core_state.amplitude = feedback.amplitude;
+                self.core_state.effective_command = feedback
+                    .effective_command
+                    .clone()
+                    .map(|command| MotionCommand { position: coordinates.from_drive(command.position), ..command });
                 self.core_state.warnings = feedback.warning_flags.iter().map(|flag| flag.to_string()).collect();
                 self.core_state.error_code = match feedback.error_code {
                     ErrorCode::NoError => None,
                     error_code => Some(error_code.to_string()),
                 };
 
+                self.core_state.guard_violation = feedback.guard_violation.map(|violation| match violation {
+                    GuardViolation::Target { position } => {
+                        GuardViolation::Target { position: coordinates.from_drive(position) }
+                    }
+                    GuardViolation::Position { position } => {
+                        GuardViolation::Position { position: coordinates.from_drive(position) }
+                    }
+                });
+
+                self.core_state.motion_fault = feedback.motion_fault;
+
+                let new_current_limit_event = feedback.current_limit_events != self.current_limit_events;
+                self.current_limit_events = feedback.current_limit_events;
+                let current_limit_event =
+                    feedback.current_limit_event.filter(|_| new_current_limit_event).map(|event| CurrentLimitEvent {
+                        current: Current(event.current.0 * coordinates.direction() as i16),
+                        position: coordinates.from_drive(event.position),
+                        ..event
+                    });
+                if let Some(event) = current_limit_event {
+                    self.core_state.current_limit_event = Some(event);
+                    self.send(None, CoreMessage::CurrentLimitExceeded { event })?;
+                }
+                let current_limit_paused =
+                    current_limit_event.is_some_and(|event| event.action == CurrentLimitAction::Pause);
+
+                // The drive has already stopped itself, leave it paused rather than moving again once reset.
+                if (feedback.guard_violation.is_some() || feedback.motion_fault.is_some() || current_limit_paused)
+                    && matches!(feedback.drive_state, DriveState::Moving | DriveState::Manual)
+                {
+                    self.drive.interface.update_commands(|commands| {
+                        commands.motion_enabled = false;
+                        commands.manual = None;
+                    });
+                }
+
+                self.update_thermal_status(&feedback);
+
+                // The soft stop has shrunk the stroke, now stop for real.
+                if feedback.soft_stopped && feedback.drive_state == DriveState::Moving {
+                    self.drive.interface.send_actions(ACTION_RESET_INDEX);
+                    self.drive.interface.update_commands(|commands| {
+                        commands.motion_enabled = false;
+                        commands.soft_stop = false;
+                    });
+                }
+
+                // Once the final repetition has settled, carry out the end action.
+                if feedback.program_complete && feedback.drive_state == DriveState::Moving {
+                    let power_off = self.core_state.repetition.end_action == EndAction::PowerOff;
+                    self.drive.interface.update_commands(|commands| {
+                        commands.motion_enabled = false;
+                        commands.power_enabled = commands.power_enabled && !power_off;
+                    });
+                }
+
+                self.update_playlist(&feedback)?;
+
+                // Vary the commands the drive has left behind for their next pass.
+                let version = self.active_command_set.0;
+                if self.core_state.variation.is_some()
+                    && self.variation_passes.update(version, feedback.active_command_index)
+                {
+                    self.sync_commands_to_drive();
+                }
+
                 self.send(None, CoreMessage::State { seq: None, state: self.core_state.clone() })
             }
             CoreEvent::HidInputReport(report) => {
@@ -315,10 +553,17 @@ impl CoreManager {
             }
             ClientMessage::GetCommandSet { seq, set } => {
                 if let Some(set_name) = &set {
-                    if let Some((version, commands)) = self.saved_command_sets.get(set_name) {
+                    if let Some(saved) = self.saved_command_sets.get(set_name) {
                         self.send(
                             Some(controller_id),
-                            CoreMessage::CommandSet { seq, set, version: *version, commands: commands.clone() },
+                            CoreMessage::CommandSet {
+                                seq,
+                                set,
+                                version: saved.version,
+                                commands: saved.commands.clone(),
+                                caps: saved.caps,
+                                units: saved.units,
+                            },
                         )
                     } else {
                         self.send(
@@ -328,33 +573,89 @@ impl CoreManager {
                     }
                 } else {
                     let (version, commands) = self.active_command_set.clone();
-                    self.send(Some(controller_id), CoreMessage::CommandSet { seq, set, version, commands })
+                    let caps = self.core_state.command_set_caps;
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::CommandSet { seq, set, version, commands, caps, units: CommandUnits::Absolute },
+                    )
                 }
             }
-            ClientMessage::UpsertCommandSet { seq, set, base_version, commands: new_commands } => {
+            ClientMessage::UpsertCommandSet { seq, set, base_version, commands: new_commands, caps, units } => {
+                let caps = caps.unwrap_or_else(|| match &set {
+                    Some(set_name) => self.saved_command_sets.get(set_name).map(|saved| saved.caps).unwrap_or_default(),
+                    None => self.core_state.command_set_caps,
+                });
+                if !caps.is_valid(&self.limits) {
+                    return self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                    );
+                }
+
+                // Relative sets are checked as they would load here, and the active set is only ever absolute.
+                let resolved = self.resolve_commands(&new_commands, &caps, units);
+                let issues = self.check_commands(&resolved, &caps);
+
                 if let Some(set_name) = &set {
-                    if let Some((version, commands)) = self.saved_command_sets.get_mut(set_name) {
-                        if base_version.is_none() || base_version == Some(*version) {
-                            *version += 1;
-                            *commands = new_commands;
-                            let version = *version;
-                            self.send(Some(controller_id), CoreMessage::CommandResult { seq, success: true, version })
+                    if !issues.errors.is_empty() {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack {
+                                seq,
+                                success: false,
+                                reason: Some(AckFailureReason::OutOfLimits(issues.errors)),
+                            },
+                        );
+                    }
+
+                    if let Some(saved) = self.saved_command_sets.get_mut(set_name) {
+                        if base_version.is_none() || base_version == Some(saved.version) {
+                            saved.version += 1;
+                            saved.commands = new_commands;
+                            saved.caps = caps;
+                            saved.units = units;
+                            let version = saved.version;
+                            self.send(
+                                Some(controller_id),
+                                CoreMessage::CommandResult { seq, success: true, version, warnings: issues.warnings },
+                            )
                         } else {
-                            let version = *version;
-                            self.send(Some(controller_id), CoreMessage::CommandResult { seq, success: false, version })
+                            let version = saved.version;
+                            self.send(
+                                Some(controller_id),
+                                CoreMessage::CommandResult { seq, success: false, version, warnings: Vec::new() },
+                            )
                         }
                     } else {
-                        self.saved_command_sets.insert(set_name.clone(), (1, new_commands.clone()));
+                        self.saved_command_sets
+                            .insert(set_name.clone(), SavedSet { version: 1, commands: new_commands, caps, units });
 
-                        self.send(Some(controller_id), CoreMessage::CommandResult { seq, success: true, version: 1 })
+                        self.send(
+                            Some(controller_id),
+                            CoreMessage::CommandResult { seq, success: true, version: 1, warnings: issues.warnings },
+                        )
                     }
                 } else if self.core_state.write_access_holder == Some(controller_id) {
+                    if !issues.errors.is_empty() {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack {
+                                seq,
+                                success: false,
+                                reason: Some(AckFailureReason::OutOfLimits(issues.errors)),
+                            },
+                        );
+                    }
+
                     if base_version.is_none() || base_version == Some(self.active_command_set.0) {
-                        self.active_command_set = (self.active_command_set.0 + 1, new_commands);
+                        self.active_command_set = (self.active_command_set.0 + 1, resolved);
+                        self.core_state.command_set_caps = caps;
+                        self.core_state.pattern = None;
+                        self.core_state.playlist_progress = None;
 
                         self.sync_commands_to_drive();
 
-                        self.drive.interface.actions.send(ACTION_RESET_INDEX);
+                        self.start_replaced_set();
 
                         self.send(
                             None,
@@ -363,12 +664,22 @@ impl CoreManager {
 
                         self.send(
                             Some(controller_id),
-                            CoreMessage::CommandResult { seq, success: true, version: self.active_command_set.0 },
+                            CoreMessage::CommandResult {
+                                seq,
+                                success: true,
+                                version: self.active_command_set.0,
+                                warnings: issues.warnings,
+                            },
                         )
                     } else {
                         self.send(
                             Some(controller_id),
-                            CoreMessage::CommandResult { seq, success: false, version: self.active_command_set.0 },
+                            CoreMessage::CommandResult {
+                                seq,
+                                success: false,
+                                version: self.active_command_set.0,
+                                warnings: Vec::new(),
+                            },
                         )
                     }
                 } else {
@@ -380,32 +691,50 @@ impl CoreManager {
             }
             ClientMessage::UpdateCommand { seq, update } => {
                 if self.core_state.write_access_holder == Some(controller_id) {
-                    match self.active_command_set.1.get_mut(update.index) {
-                        Some(command) => {
-                            if command.apply_fields(&update.fields) {
-                                self.active_command_set.0 += 1;
-
-                                self.sync_commands_to_drive();
-
-                                self.send(
-                                    None,
-                                    CoreMessage::CommandSetChanged {
-                                        version: self.active_command_set.0,
-                                        update: Some(update),
-                                    },
-                                )?;
-                            }
-
-                            self.send(
-                                Some(controller_id),
-                                CoreMessage::CommandResult { seq, success: true, version: self.active_command_set.0 },
-                            )
-                        }
-                        None => self.send(
+                    let mut commands = self.active_command_set.1.clone();
+                    let Some(command) = commands.get_mut(update.index) else {
+                        return self.send(
                             Some(controller_id),
                             CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
-                        ),
+                        );
+                    };
+                    let changed = command.apply_fields(&update.fields);
+
+                    // Only the updated command has to be within the limits, so others can still be fixed one by one.
+                    let mut issues = self.check_commands(&commands, &self.core_state.command_set_caps);
+                    issues.errors.retain(|issue| issue.index == update.index);
+                    if !issues.errors.is_empty() {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack {
+                                seq,
+                                success: false,
+                                reason: Some(AckFailureReason::OutOfLimits(issues.errors)),
+                            },
+                        );
                     }
+
+                    if changed {
+                        self.active_command_set = (self.active_command_set.0 + 1, commands);
+                        self.core_state.pattern = None;
+
+                        self.sync_commands_to_drive();
+
+                        self.send(
+                            None,
+                            CoreMessage::CommandSetChanged { version: self.active_command_set.0, update: Some(update) },
+                        )?;
+                    }
+
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::CommandResult {
+                            seq,
+                            success: true,
+                            version: self.active_command_set.0,
+                            warnings: issues.warnings,
+                        },
+                    )
                 } else {
                     self.send(
                         Some(controller_id),
@@ -415,15 +744,20 @@ impl CoreManager {
             }
             ClientMessage::DeleteCommandSet { seq, set, base_version } => {
                 if let Some(set_name) = &set {
-                    if let Some((version, _commands)) = self.saved_command_sets.get(set_name) {
-                        if base_version.is_none() || base_version == Some(*version) {
+                    if let Some(saved) = self.saved_command_sets.get(set_name) {
+                        if base_version.is_none() || base_version == Some(saved.version) {
                             self.saved_command_sets.remove(set_name);
 
                             self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
                         } else {
                             self.send(
                                 Some(controller_id),
-                                CoreMessage::CommandResult { seq, success: false, version: *version },
+                                CoreMessage::CommandResult {
+                                    seq,
+                                    success: false,
+                                    version: saved.version,
+                                    warnings: Vec::new(),
+                                },
                             )
                         }
                     } else {
@@ -435,10 +769,12 @@ impl CoreManager {
                 } else if self.core_state.write_access_holder == Some(controller_id) {
                     if base_version.is_none() || base_version == Some(self.active_command_set.0) {
                         self.active_command_set = (self.active_command_set.0 + 1, Vec::new());
+                        self.core_state.pattern = None;
+                        self.core_state.playlist_progress = None;
 
                         self.sync_commands_to_drive();
 
-                        self.drive.interface.actions.send(ACTION_RESET_INDEX);
+                        self.drive.interface.send_actions(ACTION_RESET_INDEX);
 
                         self.send(
                             None,
@@ -447,12 +783,22 @@ impl CoreManager {
 
                         self.send(
                             Some(controller_id),
-                            CoreMessage::CommandResult { seq, success: true, version: self.active_command_set.0 },
+                            CoreMessage::CommandResult {
+                                seq,
+                                success: true,
+                                version: self.active_command_set.0,
+                                warnings: Vec::new(),
+                            },
                         )
                     } else {
                         self.send(
                             Some(controller_id),
-                            CoreMessage::CommandResult { seq, success: false, version: self.active_command_set.0 },
+                            CoreMessage::CommandResult {
+                                seq,
+                                success: false,
+                                version: self.active_command_set.0,
+                                warnings: Vec::new(),
+                            },
                         )
                     }
                 } else {
@@ -470,22 +816,186 @@ impl CoreManager {
                         sets: self
                             .saved_command_sets
                             .iter()
-                            .map(|(name, (version, _commands))| SavedSetMetadata {
+                            .map(|(name, saved)| SavedSetMetadata {
                                 name: name.clone(),
-                                version: *version,
+                                version: saved.version,
                                 saved_at: "".to_string(), // TODO
                             })
                             .collect(),
                     },
                 )
             }
+            ClientMessage::LoadCommandSet { seq, set } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    // Choosing a set by hand stops the playlist, unless the set can't be loaded.
+                    let progress = self.core_state.playlist_progress.take();
+                    match self.load_saved_set(&set) {
+                        Ok(warnings) => {
+                            self.start_replaced_set();
+
+                            self.send(
+                                None,
+                                CoreMessage::CommandSetChanged { version: self.active_command_set.0, update: None },
+                            )?;
+
+                            self.send(
+                                Some(controller_id),
+                                CoreMessage::CommandResult {
+                                    seq,
+                                    success: true,
+                                    version: self.active_command_set.0,
+                                    warnings,
+                                },
+                            )
+                        }
+                        Err(reason) => {
+                            self.core_state.playlist_progress = progress;
+                            self.send(
+                                Some(controller_id),
+                                CoreMessage::Ack { seq, success: false, reason: Some(reason) },
+                            )
+                        }
+                    }
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::SetPlaylist { seq, playlist } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    let transition = playlist.transition;
+                    let valid = (1..=self.limits.velocity.0).contains(&transition.velocity.0)
+                        && (1..=self.limits.acceleration.min(self.limits.deceleration).0)
+                            .contains(&transition.acceleration.0)
+                        && playlist.entries.iter().all(|entry| match entry.length {
+                            PlaylistLength::Duration(length) | PlaylistLength::Repetitions(length) => length > 0,
+                        });
+                    if !valid {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                        );
+                    }
+
+                    self.core_state.playlist = playlist;
+                    self.stop_playlist();
+
+                    self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::PlaylistControl { seq, action } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    let current = self.core_state.playlist_progress.map(|progress| progress.entry);
+                    let entry = match (action, current) {
+                        (PlaylistAction::Start, _) => Some(0),
+                        (PlaylistAction::Skip, Some(entry)) => playlist::next(&self.core_state.playlist, entry),
+                        (PlaylistAction::Previous, Some(entry)) => {
+                            Some(playlist::previous(&self.core_state.playlist, entry))
+                        }
+                        (PlaylistAction::Skip | PlaylistAction::Previous, None) => {
+                            return self.send(
+                                Some(controller_id),
+                                CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::InvalidState) },
+                            );
+                        }
+                        (PlaylistAction::Stop, _) => None,
+                    };
+
+                    let Some(entry) = entry else {
+                        // Stopping, or skipping past the end, leaves the current set running.
+                        self.stop_playlist();
+                        return self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None });
+                    };
+
+                    match self.play_entry(entry) {
+                        Ok(warnings) => {
+                            self.send(
+                                None,
+                                CoreMessage::CommandSetChanged { version: self.active_command_set.0, update: None },
+                            )?;
+
+                            self.send(
+                                Some(controller_id),
+                                CoreMessage::CommandResult {
+                                    seq,
+                                    success: true,
+                                    version: self.active_command_set.0,
+                                    warnings,
+                                },
+                            )
+                        }
+                        Err(reason) => self
+                            .send(Some(controller_id), CoreMessage::Ack { seq, success: false, reason: Some(reason) }),
+                    }
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::AnalyzeCommandSet { seq, commands } => {
+                // Timed as the drive would run them, with the values clamped to the system limits.
+                let (limits, coordinates) = (&self.limits, self.core_state.work_coordinates);
+                let in_stroke =
+                    |position| (Position::default()..=limits.position).contains(&coordinates.to_drive(position));
+                if !commands.iter().all(|c| in_stroke(c.position)) {
+                    return self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                    );
+                }
+
+                let commands: Vec<_> = commands
+                    .into_iter()
+                    .map(|c| MotionCommand {
+                        velocity: c.velocity.clamp(Velocity::default(), limits.velocity),
+                        acceleration: c.acceleration.clamp(Acceleration::default(), limits.acceleration),
+                        deceleration: c.deceleration.clamp(Acceleration::default(), limits.deceleration),
+                        ..c
+                    })
+                    .collect();
+
+                let Some(analysis) = trajectory::analyze(&commands, self.drive_settings.interval) else {
+                    return self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                    );
+                };
+
+                let milliseconds = |duration: Duration| duration.as_millis().try_into().unwrap_or(u32::MAX);
+                let legs = analysis
+                    .legs
+                    .iter()
+                    .map(|leg| LegTiming {
+                        duration: milliseconds(leg.duration),
+                        peak_velocity: Velocity::from_meters_per_second_f64(leg.peak_velocity),
+                        reaches_velocity: leg.reaches_velocity,
+                        early_handoff: leg.early_handoff,
+                    })
+                    .collect();
+
+                self.send(
+                    Some(controller_id),
+                    CoreMessage::CommandSetAnalysis { seq, legs, cycle_time: milliseconds(analysis.cycle_time) },
+                )
+            }
             ClientMessage::SetDrivePower { seq, enabled } => {
                 if self.core_state.write_access_holder == Some(controller_id) {
-                    {
-                        let mut commands = self.drive.interface.commands.lock().unwrap();
+                    self.drive.interface.update_commands(|commands| {
                         commands.power_enabled = enabled;
                         commands.motion_enabled = enabled && commands.motion_enabled;
-                    }
+                        if !enabled {
+                            commands.manual = None;
+                        }
+                    });
 
                     self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
                 } else {
@@ -497,11 +1007,12 @@ impl CoreManager {
             }
             ClientMessage::SetMotionState { seq, action } => {
                 if self.core_state.write_access_holder == Some(controller_id) {
+                    let cool = !self.core_state.thermal.paused;
                     let valid = match (action, self.core_state.drive_state) {
-                        (MotionAction::Start, DriveState::Paused) => true,
+                        (MotionAction::Start, DriveState::Paused) => cool,
                         (MotionAction::Pause, DriveState::Moving) => true,
-                        (MotionAction::Resume, DriveState::Paused) => true,
-                        (MotionAction::Stop, DriveState::Moving | DriveState::Paused) => true,
+                        (MotionAction::Resume, DriveState::Paused) => cool,
+                        (MotionAction::Stop, DriveState::Moving | DriveState::Paused | DriveState::Manual) => true,
                         _ => false,
                     };
 
@@ -512,18 +1023,88 @@ impl CoreManager {
                         );
                     }
 
+                    // The operator has taken over from any pending automatic resume.
+                    self.thermal_resume = false;
+
+                    // With a soft ramp, the first stop shrinks the stroke and the drive is stopped once it has.
+                    // Stopping again while that is happening stops straight away.
+                    if action == MotionAction::Stop
+                        && self.core_state.drive_state == DriveState::Moving
+                        && self.core_state.soft_ramp.is_enabled()
+                        && !self.drive.interface.commands().soft_stop
                     {
-                        let mut commands = self.drive.interface.commands.lock().unwrap();
+                        self.drive.interface.update_commands(|commands| commands.soft_stop = true);
+
+                        return self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None });
+                    }
+
+                    // Reset the index first, so the drive never sees motion enabled on a finished program.
+                    // Resuming carries on to the interrupted target, eased back in with the resume profile.
+                    match action {
+                        MotionAction::Start | MotionAction::Stop => {
+                            self.drive.interface.send_actions(ACTION_RESET_INDEX)
+                        }
+                        MotionAction::Resume => self.drive.interface.send_actions(ACTION_RESUME),
+                        MotionAction::Pause => {}
+                    }
+
+                    self.drive.interface.update_commands(|commands| {
                         commands.motion_enabled = match action {
                             MotionAction::Start | MotionAction::Resume => true,
                             MotionAction::Stop | MotionAction::Pause => false,
                         };
+                        commands.soft_stop = false;
+                        if action == MotionAction::Stop {
+                            commands.manual = None;
+                        }
+                    });
+
+                    self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::AcknowledgeError { seq } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    if self.core_state.drive_state != DriveState::Errored {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::InvalidState) },
+                        );
                     }
 
-                    if action == MotionAction::Start || action == MotionAction::Stop {
-                        self.drive.interface.actions.send(ACTION_RESET_INDEX);
+                    self.drive.interface.send_actions(ACTION_ACK_ERROR);
+
+                    self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::SetRepetition { seq, repetition } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    let valid = repetition.count != Some(0)
+                        && match repetition.end_action {
+                            EndAction::Park { position } => (Position::default()..=self.limits.position)
+                                .contains(&self.core_state.work_coordinates.to_drive(position)),
+                            EndAction::Pause | EndAction::PowerOff => true,
+                        };
+
+                    if !valid {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                        );
                     }
 
+                    self.core_state.repetition = repetition;
+                    self.sync_commands_to_drive();
+
                     self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
                 } else {
                     self.send(
@@ -532,16 +1113,282 @@ impl CoreManager {
                     )
                 }
             }
-            ClientMessage::AcknowledgeError { seq } => {
+            ClientMessage::SetWorkCoordinates { seq, coordinates } => {
                 if self.core_state.write_access_holder == Some(controller_id) {
-                    if self.core_state.drive_state != DriveState::Errored {
+                    self.set_work_coordinates(controller_id, seq, coordinates)
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::SetZeroAtCurrentPosition { seq } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    let coordinates = WorkCoordinates {
+                        offset: self.core_state.work_coordinates.to_drive(self.core_state.actual_position),
+                        ..self.core_state.work_coordinates
+                    };
+                    self.set_work_coordinates(controller_id, seq, coordinates)
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::SetPositionGuards { seq, guards } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    let drive_guards = guards.to_drive(self.core_state.work_coordinates);
+                    let lowest = drive_guards.minimum.unwrap_or_default().max(Position::default());
+                    let highest =
+                        drive_guards.maximum.map_or(self.limits.position, |maximum| maximum.min(self.limits.position));
+                    if !guards.is_valid() || lowest > highest {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                        );
+                    }
+
+                    self.core_state.position_guards = guards;
+                    self.sync_commands_to_drive();
+
+                    self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::SetCurrentLimits { seq, limits } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    if !limits.is_valid() {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                        );
+                    }
+
+                    self.core_state.current_limits = limits;
+                    self.sync_commands_to_drive();
+
+                    self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::JogVelocity { seq, velocity } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    if !matches!(self.core_state.drive_state, DriveState::Paused | DriveState::Manual)
+                        || self.core_state.thermal.paused
+                    {
                         return self.send(
                             Some(controller_id),
                             CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::InvalidState) },
                         );
                     }
 
-                    self.drive.interface.actions.send(ACTION_ACK_ERROR);
+                    // Jog towards the end of travel in the requested direction, or whatever guard is in the way.
+                    let direction = velocity.0.signum() * self.core_state.work_coordinates.direction();
+                    let current = self.core_state.work_coordinates.to_drive(self.core_state.actual_position);
+                    let end = if direction < 0 { Position::default() } else { self.limits.position };
+                    let command = MotionCommand {
+                        position: self.drive_guards().reachable(current, end),
+                        velocity: Velocity(velocity.0.saturating_abs()).min(self.limits.velocity),
+                        acceleration: self.limits.acceleration,
+                        deceleration: self.limits.deceleration,
+                        dwell: 0,
+                    };
+
+                    self.drive.interface.update_commands(|commands| {
+                        // Every jog message counts as a refresh, even if nothing else has changed.
+                        let refresh = match &commands.manual {
+                            Some(ManualMotion::Jog { refresh, .. }) => refresh.wrapping_add(1),
+                            _ => 0,
+                        };
+
+                        commands.manual = Some(ManualMotion::Jog { command, refresh });
+                    });
+
+                    self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::MoveTo { seq, position, velocity, acceleration } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    if !matches!(self.core_state.drive_state, DriveState::Paused | DriveState::Manual)
+                        || self.core_state.thermal.paused
+                    {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::InvalidState) },
+                        );
+                    }
+
+                    // It would never get there without any velocity or acceleration.
+                    if velocity.0 <= 0 || acceleration.0 <= 0 {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                        );
+                    }
+
+                    let command = MotionCommand {
+                        position: self
+                            .core_state
+                            .work_coordinates
+                            .to_drive(position)
+                            .clamp(Position::default(), self.limits.position),
+                        velocity: velocity.min(self.limits.velocity),
+                        acceleration: acceleration.min(self.limits.acceleration),
+                        deceleration: acceleration.min(self.limits.deceleration),
+                        dwell: 0,
+                    };
+
+                    self.drive.interface.update_commands(|commands| {
+                        commands.manual = Some(ManualMotion::MoveTo(command));
+                    });
+
+                    self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::SetCycleFrequency { seq, frequency } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    if frequency == Some(0) {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                        );
+                    }
+
+                    // Faster than the limits allow runs at the maximum frequency, which clients can show alongside.
+                    self.core_state.cycle_frequency = frequency;
+                    self.sync_commands_to_drive();
+
+                    self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::SetVariation { seq, variation } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    // Too much would stop the drive ever arriving.
+                    let valid = |variation: &Variation| {
+                        variation.position.0 >= 0 && variation.velocity < 100 && variation.acceleration < 100
+                    };
+                    if !variation.as_ref().is_none_or(valid) {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                        );
+                    }
+
+                    self.core_state.variation = variation;
+                    self.variation_passes = variation::Passes::default();
+                    self.sync_commands_to_drive();
+
+                    self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::SetTransitionMode { seq, mode } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    self.core_state.transition_mode = mode;
+                    self.sync_commands_to_drive();
+
+                    self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::SetPattern { seq, pattern } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    // The active set's caps carry over to the generated commands.
+                    let coordinates = self.core_state.work_coordinates;
+                    let caps = self.core_state.command_set_caps;
+                    let (lowest, highest) = caps.stroke(&self.limits, coordinates);
+                    let within_stroke =
+                        |position: Position| (lowest..=highest).contains(&coordinates.to_drive(position));
+                    let commands = patterns::expand(&pattern, &caps.narrow(&self.limits)).filter(|_| {
+                        within_stroke(pattern.centre - pattern.amplitude)
+                            && within_stroke(pattern.centre + pattern.amplitude)
+                    });
+
+                    let Some(commands) = commands else {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                        );
+                    };
+
+                    // Adjusting the running pattern edits it in place, so the drive follows it smoothly.
+                    let reset = self.core_state.pattern.is_none() || commands.len() != self.active_command_set.1.len();
+
+                    self.active_command_set = (self.active_command_set.0 + 1, commands);
+                    self.core_state.pattern = Some(pattern);
+                    self.core_state.playlist_progress = None;
+
+                    self.sync_commands_to_drive();
+
+                    if reset {
+                        self.start_replaced_set();
+                    }
+
+                    self.send(
+                        None,
+                        CoreMessage::CommandSetChanged { version: self.active_command_set.0, update: None },
+                    )?;
+
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::CommandResult {
+                            seq,
+                            success: true,
+                            version: self.active_command_set.0,
+                            warnings: Vec::new(),
+                        },
+                    )
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+            ClientMessage::SetSoftRamp { seq, soft_ramp } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    if !(1..=100).contains(&soft_ramp.initial) {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                        );
+                    }
+
+                    self.core_state.soft_ramp = soft_ramp;
+                    self.drive.interface.update_commands(|commands| commands.soft_ramp = soft_ramp);
 
                     self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
                 } else {
@@ -551,19 +1398,313 @@ impl CoreManager {
                     )
                 }
             }
+            ClientMessage::SetSpeedOverride { seq, speed_override } => {
+                if self.core_state.write_access_holder == Some(controller_id) {
+                    let range = 1..=SpeedOverride::MAX_PERCENT;
+                    if !range.contains(&speed_override.velocity) || !range.contains(&speed_override.acceleration) {
+                        return self.send(
+                            Some(controller_id),
+                            CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::OutOfRange) },
+                        );
+                    }
+
+                    self.core_state.speed_override = speed_override;
+                    self.sync_commands_to_drive();
+
+                    self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+                } else {
+                    self.send(
+                        Some(controller_id),
+                        CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::NotWriter) },
+                    )
+                }
+            }
+        }
+    }
+
+    fn set_work_coordinates(
+        &mut self,
+        controller_id: ControllerId,
+        seq: u64,
+        coordinates: WorkCoordinates,
+    ) -> Result<()> {
+        // Moving the pattern under a running drive would make it jump.
+        if matches!(self.core_state.drive_state, DriveState::Moving | DriveState::Manual) {
+            return self.send(
+                Some(controller_id),
+                CoreMessage::Ack { seq, success: false, reason: Some(AckFailureReason::InvalidState) },
+            );
+        }
+
+        self.core_state.work_coordinates = coordinates;
+        self.sync_commands_to_drive();
+
+        self.send(Some(controller_id), CoreMessage::Ack { seq, success: true, reason: None })
+    }
+
+    fn update_thermal_status(&mut self, feedback: &DriveFeedback) {
+        if feedback.drive_state == DriveState::Disconnected {
+            self.motor_model.reset();
+        } else {
+            self.motor_model.update(Instant::now(), feedback.current_draw, feedback.motor_temperature);
+        }
+        self.core_state.motor_time_to_limit = self
+            .motor_model
+            .time_to_limit(self.thermal_limits.motor.pause)
+            .map(|remaining| remaining.as_secs().try_into().unwrap_or(u32::MAX));
+
+        let previous = self.core_state.thermal;
+        self.core_state.thermal = self.thermal_limits.status(
+            previous,
+            feedback.drive_temperature,
+            feedback.motor_temperature,
+            feedback.warning_flags,
+        );
+
+        if self.core_state.thermal.derating != previous.derating {
+            // While moving, the drive ramps to the derated commands at the slew rates.
+            self.sync_commands_to_drive();
+        }
+
+        if self.core_state.thermal.paused && !previous.paused {
+            warn!("Too hot to move: {:?}, {:?}", feedback.drive_temperature, feedback.motor_temperature);
+        }
+
+        let moving = matches!(feedback.drive_state, DriveState::Moving | DriveState::Manual);
+        if self.core_state.thermal.paused && moving {
+            self.thermal_resume = self.thermal_limits.auto_resume && feedback.drive_state == DriveState::Moving;
+            self.drive.interface.update_commands(|commands| {
+                commands.motion_enabled = false;
+                commands.manual = None;
+            });
+        } else if feedback.drive_state != DriveState::Paused {
+            // Powered off or errored while cooling down, the operator needs to start again.
+            self.thermal_resume = false;
+        } else if !self.core_state.thermal.paused && self.thermal_resume {
+            self.thermal_resume = false;
+
+            info!("Resuming motion after cooling down");
+            self.drive.interface.send_actions(ACTION_RESUME);
+            self.drive.interface.update_commands(|commands| commands.motion_enabled = true);
+        }
+    }
+
+    // Make a saved set the active one, leaving the caller to start the drive on it.
+    fn load_saved_set(&mut self, name: &str) -> std::result::Result<Vec<CommandIssue>, AckFailureReason> {
+        let Some(saved) = self.saved_command_sets.get(name) else {
+            return Err(AckFailureReason::NotFound);
+        };
+
+        // Relative sets are worked out for this rig now, and the rig may have changed since saving.
+        let caps = saved.caps;
+        let commands = self.resolve_commands(&saved.commands, &caps, saved.units);
+        let issues = self.check_commands(&commands, &caps);
+        if !issues.errors.is_empty() {
+            return Err(AckFailureReason::OutOfLimits(issues.errors));
+        }
+
+        self.active_command_set = (self.active_command_set.0 + 1, commands);
+        self.core_state.command_set_caps = caps;
+        self.core_state.pattern = None;
+
+        self.sync_commands_to_drive();
+
+        Ok(issues.warnings)
+    }
+
+    // Load the set of a playlist entry, with the move onto its first target capped to the transition. The playlist
+    // stops if the set can't be loaded.
+    fn play_entry(&mut self, entry: usize) -> std::result::Result<Vec<CommandIssue>, AckFailureReason> {
+        let Some(name) = self.core_state.playlist.entries.get(entry).map(|entry| entry.set.clone()) else {
+            return Err(AckFailureReason::OutOfRange);
+        };
+
+        self.core_state.playlist_progress =
+            Some(PlaylistProgress { entry, transitioning: true, ..PlaylistProgress::default() });
+        self.playlist_tracker = playlist::Tracker::default();
+
+        let result = self.load_saved_set(&name);
+        match result {
+            Ok(_) => self.drive.interface.send_actions(ACTION_RESET_INDEX),
+            Err(_) => self.stop_playlist(),
+        }
+        result
+    }
+
+    // Start the drive on a new active set, in the way the transition mode calls for.
+    fn start_replaced_set(&mut self) {
+        let crossfade = matches!(self.core_state.transition_mode, TransitionMode::Crossfade { .. });
+        if crossfade && self.core_state.drive_state == DriveState::Moving {
+            self.drive.interface.send_actions(ACTION_CROSSFADE);
+        } else {
+            self.drive.interface.send_actions(ACTION_RESET_INDEX);
+        }
+    }
+
+    fn stop_playlist(&mut self) {
+        // Lift the transition's caps from the set that's left running.
+        if self.core_state.playlist_progress.take().is_some_and(|progress| progress.transitioning) {
+            self.sync_commands_to_drive();
+        }
+    }
+
+    // Follow the drive through the current playlist entry, moving on to the next once it has played for its length.
+    fn update_playlist(&mut self, feedback: &DriveFeedback) -> Result<()> {
+        let Some(mut progress) = self.core_state.playlist_progress else {
+            return Ok(());
+        };
+
+        let (transitioning, moving) = (progress.transitioning, feedback.drive_state == DriveState::Moving);
+        self.playlist_tracker.update(&mut progress, feedback.active_command_index, moving, Instant::now());
+        self.core_state.playlist_progress = Some(progress);
+
+        if !playlist::finished(&self.core_state.playlist, &progress) {
+            if transitioning && !progress.transitioning {
+                self.sync_commands_to_drive();
+            }
+            return Ok(());
+        }
+
+        let Some(entry) = playlist::next(&self.core_state.playlist, progress.entry) else {
+            info!("Playlist finished");
+            self.stop_playlist();
+            return Ok(());
+        };
+
+        match self.play_entry(entry) {
+            Ok(_) => {
+                self.send(None, CoreMessage::CommandSetChanged { version: self.active_command_set.0, update: None })
+            }
+            Err(reason) => {
+                warn!("Stopping the playlist, couldn't play entry {}: {:?}", entry, reason);
+                Ok(())
+            }
         }
     }
 
+    fn resolve_commands(
+        &self,
+        commands: &[MotionCommand],
+        caps: &CommandSetCaps,
+        units: CommandUnits,
+    ) -> Vec<MotionCommand> {
+        match units {
+            CommandUnits::Absolute => commands.to_vec(),
+            CommandUnits::Relative => {
+                relative::resolve(commands, &self.limits, caps, self.core_state.work_coordinates, &self.drive_guards())
+            }
+        }
+    }
+
+    fn check_commands(&self, commands: &[MotionCommand], caps: &CommandSetCaps) -> validation::Issues {
+        validation::check(
+            commands,
+            &self.limits,
+            caps,
+            self.core_state.work_coordinates,
+            &self.drive_guards(),
+            self.drive_settings.overshoot_margin,
+        )
+    }
+
+    // Guards are set in work positions, the drive checks them in its own.
+    fn drive_guards(&self) -> PositionGuards {
+        self.core_state.position_guards.to_drive(self.core_state.work_coordinates)
+    }
+
     fn sync_commands_to_drive(&mut self) {
         self.core_state.command_set_version = self.active_command_set.0;
 
-        let mut commands = self.drive.interface.commands.lock().unwrap();
-        commands.commands.clear();
-        commands.commands.extend(self.active_command_set.1.iter().map(|c| MotionCommand {
-            position: c.position.clamp(Position::default(), self.limits.position),
-            velocity: c.velocity.clamp(Velocity::default(), self.limits.velocity),
-            acceleration: c.acceleration.clamp(Acceleration::default(), self.limits.acceleration),
-            deceleration: c.deceleration.clamp(Acceleration::default(), self.limits.deceleration),
-        }));
+        let (active_command_set, guards) = (&self.active_command_set, &self.drive_guards());
+        let (repetition, coordinates) = (self.core_state.repetition, self.core_state.work_coordinates);
+        let current_limits = self.core_state.current_limits.to_drive(coordinates);
+
+        // The command set's caps narrow the system limits, and targets are kept within the soft limits too.
+        let caps = self.core_state.command_set_caps;
+        let limits = &caps.narrow(&self.limits);
+        let (lowest, highest) = guards.narrow(caps.stroke(&self.limits, coordinates));
+
+        let positioned: Vec<_> = active_command_set
+            .1
+            .iter()
+            .map(|c| MotionCommand { position: coordinates.to_drive(c.position).clamp(lowest, highest), ..c.clone() })
+            .collect();
+
+        // A target cycle frequency takes the place of the speed override.
+        let timing = CycleTiming::new(&positioned, limits, self.drive_settings.interval);
+        self.core_state.max_cycle_frequency = timing.as_ref().map(|timing| (timing.max_frequency() * 1_000.0) as u32);
+
+        let (velocity_scale, acceleration_scale) = match (self.core_state.cycle_frequency, &timing) {
+            (Some(frequency), Some(timing)) => {
+                let scale = timing.speed_scale(f64::from(frequency) / 1_000.0);
+                (scale, scale * scale)
+            }
+            _ => {
+                let speed_override = self.core_state.speed_override;
+                (f64::from(speed_override.velocity) / 100.0, f64::from(speed_override.acceleration) / 100.0)
+            }
+        };
+
+        // Thermal derating applies on top of either.
+        let derating = f64::from(self.core_state.thermal.derating) / 100.0;
+        let (velocity_scale, acceleration_scale) = (velocity_scale * derating, acceleration_scale * derating);
+
+        // Variation is drawn afresh for each pass, within the same stroke and limits, and doesn't change the timing.
+        let (variation, passes) = (self.core_state.variation, &self.variation_passes);
+        let positioned = positioned.into_iter().enumerate().map(|(index, c)| match &variation {
+            Some(variation) => {
+                let varied = variation::vary(&c, variation, index, passes.pass(index));
+                MotionCommand { position: varied.position.clamp(lowest, highest), ..varied }
+            }
+            None => c,
+        });
+
+        let crossfade = match self.core_state.transition_mode {
+            TransitionMode::Immediate => Duration::ZERO,
+            TransitionMode::Crossfade { duration } => Duration::from_millis(duration.into()),
+        };
+
+        let playlist = (&self.core_state.playlist, self.core_state.playlist_progress);
+        let transition = playlist.1.filter(|progress| progress.transitioning).map(|_| playlist.0.transition);
+
+        self.drive.interface.update_commands(|commands| {
+            commands.commands.clear();
+            commands.commands.extend(positioned.map(|c| {
+                MotionCommand {
+                    velocity: Velocity(scale(c.velocity.0, velocity_scale)).clamp(Velocity::default(), limits.velocity),
+                    acceleration: Acceleration(scale(c.acceleration.0, acceleration_scale))
+                        .clamp(Acceleration::default(), limits.acceleration),
+                    deceleration: Acceleration(scale(c.deceleration.0, acceleration_scale))
+                        .clamp(Acceleration::default(), limits.deceleration),
+                    ..c
+                }
+            }));
+
+            // Ease onto the first target of a playlist entry's set from wherever the previous set left off.
+            if let (Some(transition), Some(first)) = (transition, commands.commands.first_mut()) {
+                first.velocity = first.velocity.min(transition.velocity);
+                first.acceleration = first.acceleration.min(transition.acceleration);
+                first.deceleration = first.deceleration.min(transition.acceleration);
+            }
+
+            commands.repetitions = repetition.count;
+            commands.crossfade = crossfade;
+            commands.guards.clone_from(guards);
+            commands.current_limits = current_limits;
+
+            // Park using the motion parameters of the last command.
+            commands.park = match (repetition.end_action, commands.commands.last()) {
+                (EndAction::Park { position }, Some(last)) => Some(MotionCommand {
+                    position: coordinates.to_drive(position).clamp(lowest, highest),
+                    dwell: 0,
+                    ..*last
+                }),
+                _ => None,
+            };
+        });
     }
 }
+
+fn scale(value: i32, factor: f64) -> i32 {
+    (f64::from(value) * factor).round() as i32
+}
@@ -1,5 +1,5 @@
 use crate::{ControllerId, CoreState, SystemLimits};
-use linmot::mci::units::{Acceleration, Position, Velocity};
+use linmot::mci::units::{Acceleration, Current, Position, Velocity};
 use serde::{Deserialize, Serialize};
 
 // ---------------------------------------------------------------------------
@@ -17,6 +17,7 @@ pub enum DriveState {
     Preparing,
     Paused,
     Moving,
+    Manual,
     Errored,
 }
 
@@ -32,6 +33,14 @@ pub struct MotionCommand {
     pub acceleration: Acceleration,
     #[cfg_attr(test, ts(as = "i32"))]
     pub deceleration: Acceleration,
+    /// Time to hold at the target after arriving, in milliseconds. Zero passes straight through.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    #[cfg_attr(test, ts(optional, as = "Option<u32>"))]
+    pub dwell: u32,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
 }
 
 impl MotionCommand {
@@ -61,6 +70,12 @@ impl MotionCommand {
                 changed = true;
             }
         }
+        if let Some(dwell) = fields.dwell {
+            if self.dwell != dwell {
+                self.dwell = dwell;
+                changed = true;
+            }
+        }
         changed
     }
 }
@@ -81,6 +96,86 @@ pub struct MotionCommandFields {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[cfg_attr(test, ts(as = "Option<i32>"))]
     pub deceleration: Option<Acceleration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dwell: Option<u32>,
+}
+
+/// Limits that a command set carries with it, which can only narrow the system limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(optional_fields, export_to = "bindings.ts"))]
+pub struct CommandSetCaps {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(test, ts(as = "Option<i32>"))]
+    pub velocity: Option<Velocity>,
+    /// Applies to both acceleration and deceleration.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(test, ts(as = "Option<i32>"))]
+    pub acceleration: Option<Acceleration>,
+    /// Lowest position the set may go to, in work coordinates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(test, ts(as = "Option<i32>"))]
+    pub minimum: Option<Position>,
+    /// Highest position the set may go to, in work coordinates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(test, ts(as = "Option<i32>"))]
+    pub maximum: Option<Position>,
+}
+
+impl CommandSetCaps {
+    pub fn is_valid(&self, limits: &SystemLimits) -> bool {
+        let velocity = self.velocity.is_none_or(|velocity| velocity.0 > 0 && velocity <= limits.velocity);
+        let acceleration = self.acceleration.is_none_or(|acceleration| {
+            acceleration.0 > 0 && acceleration <= limits.acceleration.max(limits.deceleration)
+        });
+        let stroke = match (self.minimum, self.maximum) {
+            (Some(minimum), Some(maximum)) => minimum <= maximum,
+            _ => true,
+        };
+
+        velocity && acceleration && stroke
+    }
+
+    /// The system limits narrowed by the caps, other than the stroke.
+    pub fn narrow(&self, limits: &SystemLimits) -> SystemLimits {
+        SystemLimits {
+            position: limits.position,
+            velocity: self.velocity.map_or(limits.velocity, |velocity| velocity.min(limits.velocity)),
+            acceleration: self.acceleration.map_or(limits.acceleration, |cap| cap.min(limits.acceleration)),
+            deceleration: self.acceleration.map_or(limits.deceleration, |cap| cap.min(limits.deceleration)),
+        }
+    }
+
+    /// The lowest and highest drive positions the set may go to, within the system limits.
+    pub fn stroke(&self, limits: &SystemLimits, coordinates: WorkCoordinates) -> (Position, Position) {
+        let (minimum, maximum) = (
+            self.minimum.map(|minimum| coordinates.to_drive(minimum)),
+            self.maximum.map(|maximum| coordinates.to_drive(maximum)),
+        );
+        let (lowest, highest) = if coordinates.inverted { (maximum, minimum) } else { (minimum, maximum) };
+
+        let lowest = lowest.map_or(Position::default(), |lowest| lowest.clamp(Position::default(), limits.position));
+        let highest = highest.map_or(limits.position, |highest| highest.clamp(lowest, limits.position));
+        (lowest, highest)
+    }
+}
+
+/// How the positions and velocities of a command set are expressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum CommandUnits {
+    /// Work positions and velocities, as they are run.
+    #[default]
+    Absolute,
+    /// Positions in parts per million of the usable stroke, from its lowest work position, and velocities in parts
+    /// per million of the velocity limit, worked out for the rig when the set is loaded.
+    Relative,
+}
+
+impl CommandUnits {
+    pub fn is_absolute(&self) -> bool {
+        *self == Self::Absolute
+    }
 }
 
 /// Metadata for a saved command set, as returned in listings.
@@ -92,6 +187,58 @@ pub struct SavedSetMetadata {
     pub saved_at: String,
 }
 
+/// A field of a motion command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum CommandField {
+    Position,
+    Velocity,
+    Acceleration,
+    Deceleration,
+}
+
+/// What is wrong with a command field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum CommandIssueKind {
+    /// Beyond the system limits, with the furthest the value may go.
+    OutOfLimits { limit: i32 },
+    /// Outside the position guards' soft limits, so the drive goes to this value instead.
+    Clamped { value: i32 },
+    /// Zero, so the leg never gets to its target.
+    Zero,
+    /// The same position as the previous command, so the leg goes nowhere.
+    DuplicatePosition,
+    /// A leg shorter than the overshoot margin, which the drive may pass straight through.
+    ShortLeg,
+}
+
+/// A problem found with a command when storing a command set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct CommandIssue {
+    pub index: usize,
+    pub field: CommandField,
+    pub issue: CommandIssueKind,
+}
+
+/// How one leg of a command set runs on the drive, from the previous command's target to its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct LegTiming {
+    /// Time until the next leg takes over, including any dwell, in milliseconds.
+    pub duration: u32,
+    /// Fastest the leg actually goes.
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub peak_velocity: Velocity,
+    /// Whether the leg gets up to its command velocity.
+    pub reaches_velocity: bool,
+    /// Whether the next leg takes over before this one has stopped on its target.
+    pub early_handoff: bool,
+}
+
 /// Identifies the target command set for operations.
 ///
 /// `None` refers to the active command set (requires writer for mutations).
@@ -109,6 +256,495 @@ pub enum MotionAction {
     Stop,
 }
 
+/// What happens once the active command set has run its repetitions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum EndAction {
+    /// Stop on the last command's target and pause.
+    #[default]
+    Pause,
+    /// Move to the park position, then pause.
+    Park {
+        #[cfg_attr(test, ts(as = "i32"))]
+        position: Position,
+    },
+    /// Stop on the last command's target and power off.
+    PowerOff,
+}
+
+/// How many times the active command set runs before stopping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct Repetition {
+    /// Number of passes through the command set, or `None` to repeat until stopped.
+    pub count: Option<u32>,
+    pub end_action: EndAction,
+}
+
+/// Percentage scaling applied to every command in the active set, 100 leaving it unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct SpeedOverride {
+    pub velocity: u16,
+    /// Applies to both acceleration and deceleration.
+    pub acceleration: u16,
+}
+
+impl SpeedOverride {
+    pub const MAX_PERCENT: u16 = 200;
+}
+
+impl Default for SpeedOverride {
+    fn default() -> Self {
+        Self { velocity: 100, acceleration: 100 }
+    }
+}
+
+/// The shape of each stroke of a generated pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum PatternShape {
+    /// Smooth strokes that speed up and slow down the whole way, like a sine wave.
+    Sine,
+    /// Constant velocity strokes with short turnarounds.
+    Triangle,
+    /// A constant velocity outward stroke, and a return as fast as the limits allow.
+    Sawtooth,
+    /// Smooth strokes out to each depth in turn, as percentages of the full stroke from the bottom.
+    MultiDepth { depths: Vec<u16> },
+}
+
+/// A repeating pattern that the core generates the active command set from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct Pattern {
+    pub shape: PatternShape,
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub centre: Position,
+    /// Distance from the centre to either end of the stroke.
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub amplitude: Position,
+    /// Strokes per second, in millihertz.
+    pub frequency: u32,
+    /// Percentage of each stroke's time spent moving outwards, towards higher positions.
+    pub symmetry: u16,
+}
+
+/// Gradual start and stop of the command set. Each position's distance from the centre of the stroke, and each
+/// velocity, is scaled from `initial` percent up to 100% after a start, and back down again before a stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct SoftRamp {
+    pub initial: u16,
+    /// How long to ramp between `initial` and 100%. Zero disables the soft start and stop.
+    pub length: RampLength,
+}
+
+impl SoftRamp {
+    pub fn is_enabled(&self) -> bool {
+        match self.length {
+            RampLength::Duration(duration) => duration > 0,
+            RampLength::Repetitions(repetitions) => repetitions > 0,
+        }
+    }
+}
+
+impl Default for SoftRamp {
+    fn default() -> Self {
+        Self { initial: 20, length: RampLength::Duration(0) }
+    }
+}
+
+/// How long a soft start or stop takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum RampLength {
+    /// Time spent ramping, in milliseconds.
+    Duration(u32),
+    /// Number of passes through the set, ramping a step at each command.
+    Repetitions(u32),
+}
+
+/// Transform between the work positions used by clients and the drive's own positions.
+///
+/// A work position is measured from `offset` on the drive, in the opposite direction if `inverted`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct WorkCoordinates {
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub offset: Position,
+    pub inverted: bool,
+}
+
+impl WorkCoordinates {
+    pub fn to_drive(&self, position: Position) -> Position {
+        if self.inverted {
+            Position(self.offset.0.saturating_sub(position.0))
+        } else {
+            Position(self.offset.0.saturating_add(position.0))
+        }
+    }
+
+    pub fn from_drive(&self, position: Position) -> Position {
+        if self.inverted {
+            Position(self.offset.0.saturating_sub(position.0))
+        } else {
+            Position(position.0.saturating_sub(self.offset.0))
+        }
+    }
+
+    /// Direction of a work velocity or acceleration on the drive, and the reverse.
+    pub fn direction(&self) -> i32 {
+        if self.inverted { -1 } else { 1 }
+    }
+
+    /// The drive positions of a range of work positions, lowest first.
+    pub fn range_to_drive(&self, start: Position, end: Position) -> (Position, Position) {
+        let (start, end) = (self.to_drive(start), self.to_drive(end));
+        (start.min(end), start.max(end))
+    }
+}
+
+/// A range of work positions that must never be entered, inclusive of both ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct KeepOutZone {
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub start: Position,
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub end: Position,
+}
+
+impl KeepOutZone {
+    pub fn contains(&self, position: Position) -> bool {
+        (self.start..=self.end).contains(&position)
+    }
+
+    /// Whether moving in a straight line between the positions passes through the zone.
+    pub fn crossed_by(&self, from: Position, to: Position) -> bool {
+        (from < self.start && to > self.end) || (from > self.end && to < self.start)
+    }
+}
+
+/// Soft position limits and keep-out zones, in work positions, within the system limits.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct PositionGuards {
+    #[cfg_attr(test, ts(as = "Option<i32>"))]
+    pub minimum: Option<Position>,
+    #[cfg_attr(test, ts(as = "Option<i32>"))]
+    pub maximum: Option<Position>,
+    pub keep_out: Vec<KeepOutZone>,
+}
+
+impl PositionGuards {
+    pub fn is_valid(&self) -> bool {
+        let limits_ordered = match (self.minimum, self.maximum) {
+            (Some(minimum), Some(maximum)) => minimum <= maximum,
+            _ => true,
+        };
+
+        limits_ordered && self.keep_out.iter().all(|zone| zone.start <= zone.end)
+    }
+
+    /// The guards in drive positions, which the rest of these work in.
+    pub fn to_drive(&self, coordinates: WorkCoordinates) -> Self {
+        let (minimum, maximum) =
+            if coordinates.inverted { (self.maximum, self.minimum) } else { (self.minimum, self.maximum) };
+
+        Self {
+            minimum: minimum.map(|position| coordinates.to_drive(position)),
+            maximum: maximum.map(|position| coordinates.to_drive(position)),
+            keep_out: self
+                .keep_out
+                .iter()
+                .map(|zone| {
+                    let (start, end) = coordinates.range_to_drive(zone.start, zone.end);
+                    KeepOutZone { start, end }
+                })
+                .collect(),
+        }
+    }
+
+    /// Narrow a range of drive positions to the soft limits.
+    pub fn narrow(&self, (lowest, highest): (Position, Position)) -> (Position, Position) {
+        let lowest = self.minimum.map_or(lowest, |minimum| minimum.max(lowest));
+        let highest = self.maximum.map_or(highest, |maximum| maximum.min(highest)).max(lowest);
+        (lowest, highest)
+    }
+
+    /// Whether the drive may be at the position.
+    pub fn allows(&self, position: Position) -> bool {
+        self.minimum.is_none_or(|minimum| position >= minimum)
+            && self.maximum.is_none_or(|maximum| position <= maximum)
+            && !self.keep_out.iter().any(|zone| zone.contains(position))
+    }
+
+    /// Whether the drive may move from one position to another. Leaving a zone is always allowed.
+    pub fn allows_move(&self, from: Position, to: Position) -> bool {
+        self.allows(to) && !self.keep_out.iter().any(|zone| zone.crossed_by(from, to))
+    }
+
+    /// The furthest position that can be reached from `from` in the direction of `to`, stopping short of
+    /// any keep-out zone in the way.
+    pub fn reachable(&self, from: Position, to: Position) -> Position {
+        let mut target = to;
+        if let Some(minimum) = self.minimum {
+            target = target.max(minimum);
+        }
+        if let Some(maximum) = self.maximum {
+            target = target.min(maximum);
+        }
+
+        for zone in &self.keep_out {
+            if from < zone.start && target >= zone.start {
+                target = Position(zone.start.0 - 1);
+            } else if from > zone.end && target <= zone.end {
+                target = Position(zone.end.0 + 1);
+            }
+        }
+
+        target
+    }
+}
+
+/// Why the drive was stopped by its position guards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum GuardViolation {
+    /// A commanded target is outside the soft limits or in a keep-out zone, or the way to it crosses one.
+    Target {
+        #[cfg_attr(test, ts(as = "i32"))]
+        position: Position,
+    },
+    /// The drive moved outside the soft limits or into a keep-out zone.
+    Position {
+        #[cfg_attr(test, ts(as = "i32"))]
+        position: Position,
+    },
+}
+
+/// Why the drive loop stopped motion after the drive failed to follow its demand position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum MotionFault {
+    /// The actual position lagged the demand position by too much for too long.
+    FollowingError {
+        #[cfg_attr(test, ts(as = "i32"))]
+        error: Position,
+    },
+    /// The demand position kept moving while the actual position did not.
+    Stall {
+        #[cfg_attr(test, ts(as = "i32"))]
+        position: Position,
+    },
+}
+
+/// What the drive loop does when the current limits are exceeded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum CurrentLimitAction {
+    /// Stop and pause.
+    #[default]
+    Pause,
+    /// Carry on towards the next command that is away from the obstruction, or stop if there isn't one.
+    Reverse,
+}
+
+/// A tighter current limit over a range of work positions, inclusive of both ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct CurrentLimitZone {
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub start: Position,
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub end: Position,
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub limit: Current,
+}
+
+/// Software force limits, as motor current magnitudes. Unset limits don't apply.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct CurrentLimits {
+    #[cfg_attr(test, ts(as = "Option<i32>"))]
+    pub absolute: Option<Current>,
+    /// Limit while pushing in the positive direction.
+    #[cfg_attr(test, ts(as = "Option<i32>"))]
+    pub positive: Option<Current>,
+    /// Limit while pushing in the negative direction.
+    #[cfg_attr(test, ts(as = "Option<i32>"))]
+    pub negative: Option<Current>,
+    pub zones: Vec<CurrentLimitZone>,
+    /// How long a limit must be exceeded for before acting, in milliseconds.
+    pub time: u32,
+    pub action: CurrentLimitAction,
+}
+
+impl CurrentLimits {
+    pub fn is_valid(&self) -> bool {
+        let positive = |limit: Option<Current>| limit.is_none_or(|limit| limit.0 > 0);
+
+        positive(self.absolute)
+            && positive(self.positive)
+            && positive(self.negative)
+            && self.zones.iter().all(|zone| zone.start <= zone.end && zone.limit.0 > 0)
+    }
+
+    /// The limits with their zones in drive positions and their directions in drive currents, which `limit_at` works
+    /// in.
+    pub fn to_drive(&self, coordinates: WorkCoordinates) -> Self {
+        let mut limits = self.clone();
+        if coordinates.inverted {
+            (limits.positive, limits.negative) = (self.negative, self.positive);
+        }
+        for zone in &mut limits.zones {
+            (zone.start, zone.end) = coordinates.range_to_drive(zone.start, zone.end);
+        }
+        limits
+    }
+
+    /// The lowest limit that applies to a current at a position, if any do.
+    pub fn limit_at(&self, position: Position, current: Current) -> Option<Current> {
+        let directional = if current.0 >= 0 { self.positive } else { self.negative };
+
+        self.zones
+            .iter()
+            .filter(|zone| (zone.start..=zone.end).contains(&position))
+            .map(|zone| zone.limit)
+            .chain(self.absolute)
+            .chain(directional)
+            .min()
+    }
+}
+
+/// The current limits being exceeded, and what was done about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct CurrentLimitEvent {
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub current: Current,
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub limit: Current,
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub position: Position,
+    pub action: CurrentLimitAction,
+}
+
+/// Thermal protection applied from the drive and motor temperatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct ThermalStatus {
+    /// Percentage of the commanded velocity and acceleration in use, 100 when not derating.
+    pub derating: u16,
+    /// Too hot to move, until cooled down to the resume temperatures.
+    pub paused: bool,
+}
+
+impl Default for ThermalStatus {
+    fn default() -> Self {
+        Self { derating: 100, paused: false }
+    }
+}
+
+/// Bounded random variation of every command in the active set, drawn afresh for each pass through it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct Variation {
+    /// Furthest a position moves either way.
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub position: Position,
+    /// Most a velocity is scaled up or down by, as a percentage.
+    pub velocity: u16,
+    /// Most the acceleration and deceleration are scaled up or down by together, as a percentage.
+    pub acceleration: u16,
+    /// The same seed varies each pass in the same way, counting passes from when the set last changed.
+    pub seed: u64,
+}
+
+/// How the drive moves onto a new active command set that replaces one it is running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum TransitionMode {
+    /// Head straight for the new set's first target.
+    #[default]
+    Immediate,
+    /// Finish the current leg, then carry on with the new set from the command after the one with the nearest target,
+    /// blending from the finished leg's velocity and accelerations over `duration` milliseconds.
+    Crossfade { duration: u32 },
+}
+
+/// How long a playlist entry plays for before moving on to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum PlaylistLength {
+    /// Time spent moving, in milliseconds.
+    Duration(u32),
+    /// Number of passes through the set.
+    Repetitions(u32),
+}
+
+/// A saved command set to play as part of a playlist.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct PlaylistEntry {
+    pub set: String,
+    pub length: PlaylistLength,
+}
+
+/// Caps on the move from wherever the previous set left off to the first target of the next.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct PlaylistTransition {
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub velocity: Velocity,
+    /// Applies to both acceleration and deceleration.
+    #[cfg_attr(test, ts(as = "i32"))]
+    pub acceleration: Acceleration,
+}
+
+/// Saved command sets played one after another.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct Playlist {
+    pub entries: Vec<PlaylistEntry>,
+    pub transition: PlaylistTransition,
+    /// Start again from the first entry after the last, rather than staying on the last set.
+    pub repeat: bool,
+}
+
+/// Playlist control action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub enum PlaylistAction {
+    Start,
+    Skip,
+    Previous,
+    Stop,
+}
+
+/// How far the playlist has got through its current entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
+pub struct PlaylistProgress {
+    pub entry: usize,
+    /// Time spent moving, in milliseconds.
+    pub elapsed: u32,
+    /// Passes completed through the set.
+    pub passes: u32,
+    /// Still on the capped move to the set's first target.
+    pub transitioning: bool,
+}
+
 /// Describes what changed in the active command set, for delta broadcasts.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
@@ -147,6 +783,13 @@ pub enum ClientMessage {
         #[cfg_attr(test, ts(optional = nullable))]
         base_version: Option<u64>,
         commands: Vec<MotionCommand>,
+        /// Replaces the set's caps, which are otherwise kept as they are.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[cfg_attr(test, ts(optional = nullable))]
+        caps: Option<CommandSetCaps>,
+        #[serde(default, skip_serializing_if = "CommandUnits::is_absolute")]
+        #[cfg_attr(test, ts(optional, as = "Option<CommandUnits>"))]
+        units: CommandUnits,
     },
     UpdateCommand {
         seq: u64,
@@ -163,6 +806,25 @@ pub enum ClientMessage {
     ListSavedSets {
         seq: u64,
     },
+    /// Replace the active command set with a saved set and its caps.
+    LoadCommandSet {
+        seq: u64,
+        set: String,
+    },
+    /// Replace the playlist, stopping it if it was playing.
+    SetPlaylist {
+        seq: u64,
+        playlist: Playlist,
+    },
+    PlaylistControl {
+        seq: u64,
+        action: PlaylistAction,
+    },
+    /// Work out how long each leg of a command set takes on the drive, without changing anything.
+    AnalyzeCommandSet {
+        seq: u64,
+        commands: Vec<MotionCommand>,
+    },
     SetDrivePower {
         seq: u64,
         enabled: bool,
@@ -174,13 +836,79 @@ pub enum ClientMessage {
     AcknowledgeError {
         seq: u64,
     },
+    SetRepetition {
+        seq: u64,
+        #[serde(flatten)]
+        repetition: Repetition,
+    },
+    SetSpeedOverride {
+        seq: u64,
+        #[serde(flatten)]
+        speed_override: SpeedOverride,
+    },
+    /// Scale the speed of the active command set so each pass through it takes the same time, in place of the speed
+    /// override. The frequency is in millihertz, or `None` to go back to the speed override.
+    SetCycleFrequency {
+        seq: u64,
+        frequency: Option<u32>,
+    },
+    /// Vary the active command set randomly, or `None` to run it as it is.
+    SetVariation {
+        seq: u64,
+        variation: Option<Variation>,
+    },
+    SetTransitionMode {
+        seq: u64,
+        mode: TransitionMode,
+    },
+    /// Replace the active command set with one generated from a pattern.
+    SetPattern {
+        seq: u64,
+        pattern: Pattern,
+    },
+    SetSoftRamp {
+        seq: u64,
+        #[serde(flatten)]
+        soft_ramp: SoftRamp,
+    },
+    SetWorkCoordinates {
+        seq: u64,
+        #[serde(flatten)]
+        coordinates: WorkCoordinates,
+    },
+    /// Move the work coordinate origin to the current position, keeping the direction.
+    SetZeroAtCurrentPosition {
+        seq: u64,
+    },
+    SetPositionGuards {
+        seq: u64,
+        guards: PositionGuards,
+    },
+    SetCurrentLimits {
+        seq: u64,
+        limits: CurrentLimits,
+    },
+    JogVelocity {
+        seq: u64,
+        #[cfg_attr(test, ts(as = "i32"))]
+        velocity: Velocity,
+    },
+    MoveTo {
+        seq: u64,
+        #[cfg_attr(test, ts(as = "i32"))]
+        position: Position,
+        #[cfg_attr(test, ts(as = "i32"))]
+        velocity: Velocity,
+        #[cfg_attr(test, ts(as = "i32"))]
+        acceleration: Acceleration,
+    },
 }
 
 // ---------------------------------------------------------------------------
 // Core → Client messages
 // ---------------------------------------------------------------------------
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[cfg_attr(test, derive(ts_rs::TS), ts(export_to = "bindings.ts"))]
 pub enum AckFailureReason {
@@ -188,6 +916,8 @@ pub enum AckFailureReason {
     NotFound,
     OutOfRange,
     InvalidState,
+    /// Commands with values beyond the system limits.
+    OutOfLimits(Vec<CommandIssue>),
 }
 
 /// All messages that the core can send to a client.
@@ -216,14 +946,34 @@ pub enum CoreMessage {
     WriteAccessResult { seq: u64, granted: bool, holder: Option<ControllerId> },
 
     /// Command set contents (response to get_command_set).
-    CommandSet { seq: u64, set: CommandSetId, version: u64, commands: Vec<MotionCommand> },
+    CommandSet {
+        seq: u64,
+        set: CommandSetId,
+        version: u64,
+        commands: Vec<MotionCommand>,
+        caps: CommandSetCaps,
+        #[serde(default, skip_serializing_if = "CommandUnits::is_absolute")]
+        #[cfg_attr(test, ts(optional, as = "Option<CommandUnits>"))]
+        units: CommandUnits,
+    },
 
     /// Result of a command set mutation or set-level operation.
-    CommandResult { seq: u64, success: bool, version: u64 },
+    CommandResult {
+        seq: u64,
+        success: bool,
+        version: u64,
+        /// Commands that were stored, but won't run quite as written.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        #[cfg_attr(test, ts(optional, as = "Option<Vec<CommandIssue>>"))]
+        warnings: Vec<CommandIssue>,
+    },
 
     /// List of saved command sets.
     SavedSetList { seq: u64, sets: Vec<SavedSetMetadata> },
 
+    /// Timing of a command set (response to analyze_command_set), with the cycle time in milliseconds.
+    CommandSetAnalysis { seq: u64, legs: Vec<LegTiming>, cycle_time: u32 },
+
     /// Real-time system state and telemetry.
     ///
     /// Used both as a response to `get_state` (with seq) and as a
@@ -246,4 +996,7 @@ pub enum CoreMessage {
 
     /// Broadcast: designated writer changed.
     WriteAccessChanged { holder: Option<ControllerId>, previous_holder: Option<ControllerId> },
+
+    /// Broadcast: the current limits were exceeded.
+    CurrentLimitExceeded { event: CurrentLimitEvent },
 }
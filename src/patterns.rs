@@ -0,0 +1,189 @@
+use crate::profile::{self, meters, meters_per_second, meters_per_second_squared};
+use puddle::SystemLimits;
+use puddle::messages::{MotionCommand, Pattern, PatternShape};
+use puddle::units::{Acceleration, Position, Velocity};
+
+// Fraction of each constant velocity stroke spent speeding up and slowing down.
+const CONSTANT_VELOCITY_RAMPS: f64 = 0.2;
+
+/// Expand a pattern into the commands for one cycle of it, in the same coordinates as the pattern.
+///
+/// Returns None if the parameters are invalid, or the strokes can't keep to the frequency within the limits.
+pub fn expand(pattern: &Pattern, limits: &SystemLimits) -> Option<Vec<MotionCommand>> {
+    if pattern.amplitude.0 <= 0 || pattern.frequency == 0 || !(1..=99).contains(&pattern.symmetry) {
+        return None;
+    }
+
+    let period = 1_000.0 / f64::from(pattern.frequency);
+    let outward = period * f64::from(pattern.symmetry) / 100.0;
+    let inward = period - outward;
+
+    let bottom = Position(pattern.centre.0.checked_sub(pattern.amplitude.0)?);
+    let top = Position(pattern.centre.0.checked_add(pattern.amplitude.0)?);
+    let stroke = Position(top.0.checked_sub(bottom.0)?);
+
+    match &pattern.shape {
+        PatternShape::Sine => {
+            Some(vec![timed(top, stroke, outward, 1.0, limits)?, timed(bottom, stroke, inward, 1.0, limits)?])
+        }
+        PatternShape::Triangle => Some(vec![
+            timed(top, stroke, outward, CONSTANT_VELOCITY_RAMPS, limits)?,
+            timed(bottom, stroke, inward, CONSTANT_VELOCITY_RAMPS, limits)?,
+        ]),
+        PatternShape::Sawtooth => {
+            let fastest = MotionCommand {
+                position: bottom,
+                velocity: limits.velocity,
+                acceleration: limits.acceleration,
+                deceleration: limits.deceleration,
+                dwell: 0,
+            };
+            let back =
+                profile::rest_to_rest_duration(stroke, limits.velocity, limits.acceleration, limits.deceleration)?;
+
+            let out = period - back.as_secs_f64();
+            if out <= 0.0 {
+                return None;
+            }
+
+            Some(vec![timed(top, stroke, out, CONSTANT_VELOCITY_RAMPS, limits)?, fastest])
+        }
+        PatternShape::MultiDepth { depths } => {
+            if depths.is_empty() || depths.iter().any(|depth| !(1..=100).contains(depth)) {
+                return None;
+            }
+
+            let mut commands = Vec::with_capacity(depths.len() * 2);
+            for &depth in depths {
+                let distance = Position((i64::from(stroke.0) * i64::from(depth) / 100) as i32);
+                commands.push(timed(bottom + distance, distance, outward, 1.0, limits)?);
+                commands.push(timed(bottom, distance, inward, 1.0, limits)?);
+            }
+            Some(commands)
+        }
+    }
+}
+
+// A command that covers the distance from a standstill to a standstill in the given number of seconds, spending
+// `ramps` of that time speeding up and slowing down at the same rate.
+fn timed(
+    target: Position,
+    distance: Position,
+    seconds: f64,
+    ramps: f64,
+    limits: &SystemLimits,
+) -> Option<MotionCommand> {
+    let velocity = meters(distance) / (seconds * (1.0 - ramps / 2.0));
+    let acceleration = velocity / (seconds * ramps / 2.0);
+
+    let limit = meters_per_second_squared(limits.acceleration.min(limits.deceleration));
+    if velocity > meters_per_second(limits.velocity) || acceleration > limit {
+        return None;
+    }
+
+    // Round up so as not to fall behind, but never past the limits.
+    let velocity = Velocity((velocity * 1_000_000.0).ceil() as i32).min(limits.velocity);
+    let acceleration =
+        Acceleration((acceleration * 100_000.0).ceil() as i32).min(limits.acceleration.min(limits.deceleration));
+
+    Some(MotionCommand { position: target, velocity, acceleration, deceleration: acceleration, dwell: 0 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const LIMITS: SystemLimits = SystemLimits {
+        position: Position::from_millimeters(360),
+        velocity: Velocity::from_meters_per_second(2),
+        acceleration: Acceleration::from_meters_per_second_squared(15),
+        deceleration: Acceleration::from_meters_per_second_squared(15),
+    };
+
+    fn pattern(shape: PatternShape, frequency: u32, symmetry: u16) -> Pattern {
+        Pattern {
+            shape,
+            centre: Position::from_millimeters(150),
+            amplitude: Position::from_millimeters(50),
+            frequency,
+            symmetry,
+        }
+    }
+
+    fn cycle_time(commands: &[MotionCommand]) -> Duration {
+        let mut position = commands.last().unwrap().position;
+        commands
+            .iter()
+            .map(|command| {
+                let distance = command.position - position;
+                position = command.position;
+                profile::rest_to_rest_duration(distance, command.velocity, command.acceleration, command.deceleration)
+                    .unwrap()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn sine_strokes_take_the_period() {
+        let commands = expand(&pattern(PatternShape::Sine, 1_000, 50), &LIMITS).unwrap();
+
+        let positions: Vec<_> = commands.iter().map(|c| c.position).collect();
+        assert_eq!(positions, [Position::from_millimeters(200), Position::from_millimeters(100)]);
+        assert!((cycle_time(&commands).as_secs_f64() - 1.0).abs() < 0.001);
+
+        // Speeding up all the way to the middle of each stroke.
+        assert_eq!(commands[0].velocity, Velocity::from_meters_per_second_f64(0.4));
+        assert_eq!(commands[0].acceleration, Acceleration::from_meters_per_second_squared_f64(1.6));
+    }
+
+    #[test]
+    fn symmetry_splits_the_period_between_strokes() {
+        let commands = expand(&pattern(PatternShape::Triangle, 500, 25), &LIMITS).unwrap();
+        assert!((cycle_time(&commands).as_secs_f64() - 2.0).abs() < 0.001);
+
+        // Three times as long going back.
+        let out = profile::rest_to_rest_duration(
+            Position::from_millimeters(100),
+            commands[0].velocity,
+            commands[0].acceleration,
+            commands[0].deceleration,
+        )
+        .unwrap();
+        assert!((out.as_secs_f64() - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn sawtooth_returns_at_the_limits() {
+        let commands = expand(&pattern(PatternShape::Sawtooth, 1_000, 50), &LIMITS).unwrap();
+        assert_eq!(commands[1].velocity, LIMITS.velocity);
+        assert!((cycle_time(&commands).as_secs_f64() - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn multi_depth_strokes_from_the_bottom() {
+        let shape = PatternShape::MultiDepth { depths: vec![50, 100] };
+        let commands = expand(&pattern(shape, 1_000, 50), &LIMITS).unwrap();
+
+        let positions: Vec<_> = commands.iter().map(|c| c.position).collect();
+        assert_eq!(
+            positions,
+            [
+                Position::from_millimeters(150),
+                Position::from_millimeters(100),
+                Position::from_millimeters(200),
+                Position::from_millimeters(100),
+            ]
+        );
+        assert!((cycle_time(&commands).as_secs_f64() - 2.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn impossible_patterns_are_rejected() {
+        // Would need 4m/s.
+        assert_eq!(expand(&pattern(PatternShape::Sine, 10_000, 50), &LIMITS), None);
+        assert_eq!(expand(&pattern(PatternShape::Sine, 0, 50), &LIMITS), None);
+        assert_eq!(expand(&pattern(PatternShape::Sine, 1_000, 100), &LIMITS), None);
+        assert_eq!(expand(&pattern(PatternShape::MultiDepth { depths: vec![] }, 1_000, 50), &LIMITS), None);
+    }
+}
@@ -0,0 +1,131 @@
+use puddle::messages::{Playlist, PlaylistLength, PlaylistProgress};
+use std::time::Instant;
+
+/// Follows the drive through the set of the current playlist entry, counting its passes and the time spent moving.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    // Command index last seen, or None until the drive has been reset onto the entry's set.
+    index: Option<usize>,
+    // When the drive was last seen moving.
+    moving_since: Option<Instant>,
+}
+
+impl Tracker {
+    /// Account for a feedback update from the drive.
+    ///
+    /// The transition is over once the first target is reached, and each time the index wraps back to the first
+    /// command is a pass. Sets of a single command never wrap, so only play for a duration.
+    pub fn update(&mut self, progress: &mut PlaylistProgress, index: usize, moving: bool, now: Instant) {
+        if let Some(since) = self.moving_since {
+            let elapsed = now.saturating_duration_since(since).as_millis();
+            progress.elapsed = progress.elapsed.saturating_add(elapsed.try_into().unwrap_or(u32::MAX));
+        }
+        self.moving_since = moving.then_some(now);
+
+        match self.index {
+            // Until the reset reaches the drive, the index is still that of the previous set.
+            None if index != 0 => {}
+            None => self.index = Some(index),
+            Some(previous) if previous != index => {
+                progress.transitioning = false;
+                if index == 0 {
+                    progress.passes += 1;
+                }
+                self.index = Some(index);
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// Whether the current entry has played for its length.
+pub fn finished(playlist: &Playlist, progress: &PlaylistProgress) -> bool {
+    match playlist.entries.get(progress.entry).map(|entry| entry.length) {
+        Some(PlaylistLength::Duration(duration)) => progress.elapsed >= duration,
+        Some(PlaylistLength::Repetitions(count)) => progress.passes >= count,
+        None => true,
+    }
+}
+
+/// The entry after this one, or None at the end of a playlist that doesn't repeat.
+pub fn next(playlist: &Playlist, entry: usize) -> Option<usize> {
+    match entry + 1 {
+        next if next < playlist.entries.len() => Some(next),
+        _ if playlist.repeat && !playlist.entries.is_empty() => Some(0),
+        _ => None,
+    }
+}
+
+/// The entry before this one, staying on the first entry unless the playlist repeats.
+pub fn previous(playlist: &Playlist, entry: usize) -> usize {
+    match entry.checked_sub(1) {
+        Some(previous) => previous,
+        None if playlist.repeat => playlist.entries.len().saturating_sub(1),
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use puddle::messages::PlaylistEntry;
+    use std::time::Duration;
+
+    fn playlist(repeat: bool) -> Playlist {
+        let entry = |set: &str, length| PlaylistEntry { set: set.to_string(), length };
+        Playlist {
+            entries: vec![entry("a", PlaylistLength::Duration(1_000)), entry("b", PlaylistLength::Repetitions(2))],
+            repeat,
+            ..Playlist::default()
+        }
+    }
+
+    #[test]
+    fn tracker_counts_passes_once_reset() {
+        let mut tracker = Tracker::default();
+        let mut progress = PlaylistProgress { entry: 1, transitioning: true, ..PlaylistProgress::default() };
+        let now = Instant::now();
+
+        // Still on the previous set's index, then reset and heading for the first target.
+        for index in [2, 0, 0] {
+            tracker.update(&mut progress, index, true, now);
+        }
+        assert!(progress.transitioning);
+
+        for index in [1, 2, 0, 1, 2, 0] {
+            tracker.update(&mut progress, index, true, now);
+        }
+        assert!(!progress.transitioning);
+        assert_eq!(progress.passes, 2);
+        assert!(finished(&playlist(false), &progress));
+    }
+
+    #[test]
+    fn tracker_only_counts_time_moving() {
+        let mut tracker = Tracker::default();
+        let mut progress = PlaylistProgress::default();
+        let start = Instant::now();
+        let at = |milliseconds| start + Duration::from_millis(milliseconds);
+
+        tracker.update(&mut progress, 0, true, at(0));
+        tracker.update(&mut progress, 0, false, at(600));
+        tracker.update(&mut progress, 0, true, at(5_000));
+        assert_eq!(progress.elapsed, 600);
+        assert!(!finished(&playlist(false), &progress));
+
+        tracker.update(&mut progress, 0, true, at(5_400));
+        assert!(finished(&playlist(false), &progress));
+    }
+
+    #[test]
+    fn entries_wrap_only_when_repeating() {
+        assert_eq!(next(&playlist(false), 0), Some(1));
+        assert_eq!(next(&playlist(false), 1), None);
+        assert_eq!(next(&playlist(true), 1), Some(0));
+        assert_eq!(next(&Playlist { repeat: true, ..Playlist::default() }, 0), None);
+
+        assert_eq!(previous(&playlist(false), 1), 0);
+        assert_eq!(previous(&playlist(false), 0), 0);
+        assert_eq!(previous(&playlist(true), 0), 1);
+    }
+}
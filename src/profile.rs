@@ -0,0 +1,219 @@
+use crate::trajectory;
+use puddle::SystemLimits;
+use puddle::messages::MotionCommand;
+use puddle::units::{Acceleration, Position, Velocity};
+use std::time::Duration;
+
+// Motion profiles of the drive's VAI commands, which accelerate at a constant rate up to the maximum velocity,
+// cruise, then decelerate at a constant rate onto the target. Calculations are in SI units.
+
+// Slowest a cycle frequency can scale the commands down to.
+const MIN_SCALE: f64 = 1e-3;
+// Halvings of the range when searching for the scale for a cycle frequency, well past the drive's resolution.
+const SCALE_SEARCH_STEPS: u32 = 30;
+
+pub fn meters(position: Position) -> f64 {
+    f64::from(position.0) / 10_000_000.0
+}
+
+pub fn meters_per_second(velocity: Velocity) -> f64 {
+    f64::from(velocity.0) / 1_000_000.0
+}
+
+pub fn meters_per_second_squared(acceleration: Acceleration) -> f64 {
+    f64::from(acceleration.0) / 100_000.0
+}
+
+/// Time for a leg of the given distance that starts and ends at a standstill, or None if it would never get there.
+pub fn rest_to_rest_duration(
+    distance: Position,
+    velocity: Velocity,
+    acceleration: Acceleration,
+    deceleration: Acceleration,
+) -> Option<Duration> {
+    let distance = meters(distance).abs();
+    if distance == 0.0 {
+        return Some(Duration::ZERO);
+    }
+
+    let (v, a, d) =
+        (meters_per_second(velocity), meters_per_second_squared(acceleration), meters_per_second_squared(deceleration));
+    if v <= 0.0 || a <= 0.0 || d <= 0.0 {
+        return None;
+    }
+
+    // Distance covered getting up to the velocity and back down again.
+    let ramps = v * v / (2.0 * a) + v * v / (2.0 * d);
+    let seconds = if ramps <= distance {
+        v / a + v / d + (distance - ramps) / v
+    } else {
+        // Never reaches the velocity, turning around where the two ramps meet.
+        let peak = (2.0 * distance * a * d / (a + d)).sqrt();
+        peak / a + peak / d
+    };
+
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// How long one pass through a command set takes, and how far it can be sped up within the limits.
+///
+/// Scaling every velocity by a factor and every acceleration by its square keeps the shape of each leg, so the cycle
+/// time falls as the factor rises. Each pass is timed as the drive runs it, handoffs included, and the factor for a
+/// cycle time is searched for. Dwells aren't scaled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleTiming {
+    commands: Vec<MotionCommand>,
+    interval: Duration,
+    max_scale: f64,
+}
+
+impl CycleTiming {
+    /// Time the commands as a cycle, going on from the last command's target to the first, checked for handoffs at
+    /// the drive's interval. None if nothing moves, or a leg would never arrive.
+    pub fn new(commands: &[MotionCommand], limits: &SystemLimits, interval: Duration) -> Option<Self> {
+        let dwelling: Duration = commands.iter().map(|command| Duration::from_millis(command.dwell.into())).sum();
+        if trajectory::analyze(commands, interval)?.cycle_time <= dwelling {
+            return None;
+        }
+
+        let ratio = |limit: f64, value: f64| limit / value;
+        let max_scale = commands
+            .iter()
+            .flat_map(|command| {
+                [
+                    ratio(meters_per_second(limits.velocity), meters_per_second(command.velocity)),
+                    ratio(
+                        meters_per_second_squared(limits.acceleration),
+                        meters_per_second_squared(command.acceleration),
+                    )
+                    .sqrt(),
+                    ratio(
+                        meters_per_second_squared(limits.deceleration),
+                        meters_per_second_squared(command.deceleration),
+                    )
+                    .sqrt(),
+                ]
+            })
+            .fold(f64::INFINITY, f64::min);
+
+        Some(Self { commands: commands.to_vec(), interval, max_scale })
+    }
+
+    /// Most cycles per second the limits allow.
+    pub fn max_frequency(&self) -> f64 {
+        1.0 / self.cycle_time(self.max_scale)
+    }
+
+    /// Factor to scale the velocities by, and the accelerations by its square, to run at the given cycles per
+    /// second, or as close as the limits allow.
+    pub fn speed_scale(&self, frequency: f64) -> f64 {
+        let period = 1.0 / frequency;
+        if self.cycle_time(self.max_scale) >= period {
+            return self.max_scale;
+        }
+
+        // Slow down until the cycle takes long enough, then narrow in on the period.
+        let mut slow = self.max_scale;
+        while self.cycle_time(slow) < period && slow > MIN_SCALE {
+            slow /= 2.0;
+        }
+
+        let mut fast = self.max_scale;
+        for _ in 0..SCALE_SEARCH_STEPS {
+            let scale = (slow + fast) / 2.0;
+            if self.cycle_time(scale) < period {
+                fast = scale;
+            } else {
+                slow = scale;
+            }
+        }
+
+        (slow + fast) / 2.0
+    }
+
+    // Seconds a pass takes with the commands scaled, as they'll be sent to the drive. Too slow to time is forever.
+    fn cycle_time(&self, scale: f64) -> f64 {
+        let scaled = |value: i32, factor: f64| (f64::from(value) * factor).round() as i32;
+        let commands: Vec<_> = self
+            .commands
+            .iter()
+            .map(|command| MotionCommand {
+                velocity: Velocity(scaled(command.velocity.0, scale)),
+                acceleration: Acceleration(scaled(command.acceleration.0, scale * scale)),
+                deceleration: Acceleration(scaled(command.deceleration.0, scale * scale)),
+                ..command.clone()
+            })
+            .collect();
+
+        trajectory::analyze(&commands, self.interval)
+            .map_or(f64::INFINITY, |analysis| analysis.cycle_time.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rest_to_rest_cruises_at_velocity() {
+        // 0.25s up to 1m/s and 0.25s down cover 0.25m, leaving 0.75m at 1m/s.
+        let duration = rest_to_rest_duration(
+            Position::from_millimeters(1_000),
+            Velocity::from_meters_per_second(1),
+            Acceleration::from_meters_per_second_squared(4),
+            Acceleration::from_meters_per_second_squared(4),
+        );
+        assert_eq!(duration, Some(Duration::from_millis(1_250)));
+    }
+
+    #[test]
+    fn rest_to_rest_turns_around_before_velocity() {
+        // Peaks at 0.4m/s after 0.1s, then takes 0.1s to stop.
+        let duration = rest_to_rest_duration(
+            Position::from_millimeters(-40),
+            Velocity::from_meters_per_second(1),
+            Acceleration::from_meters_per_second_squared(4),
+            Acceleration::from_meters_per_second_squared(4),
+        )
+        .unwrap();
+        assert!((duration.as_secs_f64() - 0.2).abs() < 1e-9);
+
+        assert_eq!(
+            rest_to_rest_duration(Position(1), Velocity(0), Acceleration(1), Acceleration(1)),
+            None,
+            "never moves without any velocity",
+        );
+    }
+
+    #[test]
+    fn cycle_timing_solves_for_frequency() {
+        const INTERVAL: Duration = Duration::from_millis(2);
+        const LIMITS: SystemLimits = SystemLimits {
+            position: Position::from_millimeters(360),
+            velocity: Velocity::from_meters_per_second(2),
+            acceleration: Acceleration::from_meters_per_second_squared(16),
+            deceleration: Acceleration::from_meters_per_second_squared(16),
+        };
+
+        // Two of the 1.25s legs above, stopping on each target for a quarter of a second.
+        let command = |position, dwell| MotionCommand {
+            position: Position::from_millimeters(position),
+            velocity: Velocity::from_meters_per_second(1),
+            acceleration: Acceleration::from_meters_per_second_squared(4),
+            deceleration: Acceleration::from_meters_per_second_squared(4),
+            dwell,
+        };
+        let timing = CycleTiming::new(&[command(0, 250), command(1_000, 250)], &LIMITS, INTERVAL).unwrap();
+
+        // Three seconds a cycle as it is, and two seconds gives a second and a half to move in.
+        assert!((timing.speed_scale(1.0 / 3.0) - 1.0).abs() < 1e-3);
+        assert!((timing.speed_scale(0.5) - 2.5 / 1.5).abs() < 1e-3);
+
+        // Twice as fast reaches both the velocity and acceleration limits.
+        assert!((timing.max_frequency() - 1.0 / 1.75).abs() < 1e-6);
+        assert!((timing.speed_scale(1.0) - 2.0).abs() < 1e-9);
+        assert!((timing.speed_scale(4.0) - 2.0).abs() < 1e-9, "no time left after dwelling");
+
+        assert_eq!(CycleTiming::new(&[command(0, 500)], &LIMITS, INTERVAL), None, "nothing moves");
+    }
+}
@@ -0,0 +1,104 @@
+use puddle::SystemLimits;
+use puddle::messages::{CommandSetCaps, MotionCommand, PositionGuards, WorkCoordinates};
+use puddle::units::{Position, Velocity};
+
+// Relative positions and velocities are in parts per million.
+const WHOLE: i64 = 1_000_000;
+
+/// Work out the commands of a relative command set for this rig.
+///
+/// Positions span the usable stroke, the system stroke narrowed by the set's caps and the soft limits, from its lowest
+/// work position to its highest. Velocities are a share of the velocity limit, as narrowed by the caps. Values past
+/// the whole of either are left for the limit checks to catch.
+pub fn resolve(
+    commands: &[MotionCommand],
+    limits: &SystemLimits,
+    caps: &CommandSetCaps,
+    coordinates: WorkCoordinates,
+    guards: &PositionGuards,
+) -> Vec<MotionCommand> {
+    let (lowest, highest) = guards.narrow(caps.stroke(limits, coordinates));
+    let (start, end) = (coordinates.from_drive(lowest), coordinates.from_drive(highest));
+    let (bottom, stroke) = (i64::from(start.0.min(end.0)), i64::from(start.0.abs_diff(end.0)));
+    let velocity = i64::from(caps.narrow(limits).velocity.0);
+
+    let share = |whole: i64, parts: i32| (whole * i64::from(parts) + WHOLE / 2).div_euclid(WHOLE);
+    commands
+        .iter()
+        .map(|command| MotionCommand {
+            position: Position(
+                (bottom + share(stroke, command.position.0)).clamp(i32::MIN.into(), i32::MAX.into()) as i32
+            ),
+            velocity: Velocity(share(velocity, command.velocity.0).clamp(i32::MIN.into(), i32::MAX.into()) as i32),
+            ..command.clone()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use puddle::units::Acceleration;
+
+    const LIMITS: SystemLimits = SystemLimits {
+        position: Position::from_millimeters(360),
+        velocity: Velocity::from_meters_per_second(2),
+        acceleration: Acceleration::from_meters_per_second_squared(15),
+        deceleration: Acceleration::from_meters_per_second_squared(15),
+    };
+
+    fn relative(position: i32, velocity: i32) -> MotionCommand {
+        MotionCommand {
+            position: Position(position),
+            velocity: Velocity(velocity),
+            acceleration: Acceleration::from_meters_per_second_squared(10),
+            deceleration: Acceleration::from_meters_per_second_squared(10),
+            dwell: 100,
+        }
+    }
+
+    #[test]
+    fn relative_commands_span_the_usable_stroke() {
+        // Usable from 60mm to 300mm on the drive, which is -20mm to 220mm in work coordinates.
+        let guards = PositionGuards {
+            minimum: Some(Position::from_millimeters(60)),
+            maximum: Some(Position::from_millimeters(300)),
+            ..PositionGuards::default()
+        };
+        let coordinates = WorkCoordinates { offset: Position::from_millimeters(280), inverted: true };
+
+        let commands = [relative(0, 250_000), relative(500_000, 1_000_000), relative(1_000_000, 0)];
+        let resolved = resolve(&commands, &LIMITS, &CommandSetCaps::default(), coordinates, &guards);
+
+        let values: Vec<_> = resolved.iter().map(|command| (command.position, command.velocity)).collect();
+        assert_eq!(
+            values,
+            [
+                (Position::from_millimeters(-20), Velocity::from_meters_per_second_f64(0.5)),
+                (Position::from_millimeters(100), Velocity::from_meters_per_second(2)),
+                (Position::from_millimeters(220), Velocity(0)),
+            ]
+        );
+        assert_eq!(resolved[0].dwell, 100);
+    }
+
+    #[test]
+    fn relative_commands_follow_the_caps() {
+        let caps = CommandSetCaps {
+            velocity: Some(Velocity::from_meters_per_second(1)),
+            minimum: Some(Position::from_millimeters(100)),
+            maximum: Some(Position::from_millimeters(200)),
+            ..CommandSetCaps::default()
+        };
+
+        let resolved = resolve(
+            &[relative(250_000, 500_000)],
+            &LIMITS,
+            &caps,
+            WorkCoordinates::default(),
+            &PositionGuards::default(),
+        );
+        assert_eq!(resolved[0].position, Position::from_millimeters(125));
+        assert_eq!(resolved[0].velocity, Velocity::from_meters_per_second_f64(0.5));
+    }
+}
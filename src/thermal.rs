@@ -0,0 +1,331 @@
+use linmot::mci::WarningFlags;
+use linmot::mci::units::{Current, DriveTemperature, MotorTemperature};
+use puddle::messages::ThermalStatus;
+use std::time::{Duration, Instant};
+
+// How long the current and temperature are averaged over for each step of the motor model.
+const MODEL_WINDOW: Duration = Duration::from_secs(10);
+// Weight given to earlier windows for each new one when estimating the heating gain.
+const MODEL_FORGETTING: f64 = 0.99;
+// Temperatures the drive reports over, in °C.
+const SENSOR_RANGE: (f64, f64) = (-50.0, 200.0);
+
+// Temperatures at which one sensor starts derating, reaches the minimum derating and pauses, and may resume again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds<T> {
+    pub derate: T,
+    pub pause: T,
+    pub resume: T,
+}
+
+// Thermal protection configuration, fixed for the lifetime of the core.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThermalLimits {
+    pub drive: Thresholds<DriveTemperature>,
+    pub motor: Thresholds<MotorTemperature>,
+    // Derating percentage reached at the pause temperature.
+    pub minimum_derating: u16,
+    // Carry on moving once cooled down after a thermal pause, rather than waiting for a start.
+    pub auto_resume: bool,
+}
+
+impl ThermalLimits {
+    /// Whether derating starts and resuming is allowed below the pause temperature for each sensor, all within the
+    /// range the drive reports, and the minimum derating is a percentage.
+    pub fn is_valid(&self) -> bool {
+        let in_range = |celsius: f64| celsius > SENSOR_RANGE.0 && celsius < SENSOR_RANGE.1;
+        let ordered = |derate: f64, pause: f64, resume: f64| {
+            derate < pause && resume < pause && [derate, pause, resume].into_iter().all(in_range)
+        };
+
+        ordered(
+            self.drive.derate.to_celsius_f64(),
+            self.drive.pause.to_celsius_f64(),
+            self.drive.resume.to_celsius_f64(),
+        ) && ordered(
+            self.motor.derate.to_celsius_f64(),
+            self.motor.pause.to_celsius_f64(),
+            self.motor.resume.to_celsius_f64(),
+        ) && self.minimum_derating <= 100
+    }
+
+    /// Work out the thermal status for the latest temperatures, given the previous status.
+    ///
+    /// Derating falls linearly from 100% at the derate temperature to the minimum at the pause temperature, using
+    /// whichever sensor is worse. The drive's own hot warnings pause straight away, and a pause is held until both
+    /// temperatures are back below their resume temperatures.
+    pub fn status(
+        &self,
+        previous: ThermalStatus,
+        drive: DriveTemperature,
+        motor: MotorTemperature,
+        warnings: WarningFlags,
+    ) -> ThermalStatus {
+        let hot_warning = warnings.intersects(WarningFlags::MOTOR_HOT_SENSOR | WarningFlags::DRIVE_HOT);
+        let too_hot = hot_warning || drive >= self.drive.pause || motor >= self.motor.pause;
+        let cooled = !hot_warning && drive < self.drive.resume && motor < self.motor.resume;
+
+        let drive_derating = self.derating(drive.0, self.drive.derate.0, self.drive.pause.0);
+        let motor_derating = self.derating(motor.0, self.motor.derate.0, self.motor.pause.0);
+
+        ThermalStatus { derating: drive_derating.min(motor_derating), paused: too_hot || (previous.paused && !cooled) }
+    }
+
+    fn derating(&self, temperature: i16, derate: i16, pause: i16) -> u16 {
+        if temperature <= derate {
+            return 100;
+        }
+        if temperature >= pause {
+            return self.minimum_derating;
+        }
+
+        let fraction = f64::from(temperature - derate) / f64::from(pause - derate);
+        (100.0 - fraction * f64::from(100 - self.minimum_derating)).round() as u16
+    }
+}
+
+// First order model of the motor heating, where the temperature rises towards the ambient temperature plus a gain
+// times the square of the current, at a rate set by the motor's thermal time constant.
+//
+// The gain is estimated from how the measured temperature follows the current, then the mean square current of
+// the most recent window predicts where the current pattern will take the temperature, and how soon.
+#[derive(Debug, Clone)]
+pub struct MotorModel {
+    time_constant: Duration,
+    // First temperature read since the model was started or reset, taken as the ambient temperature.
+    ambient: Option<f64>,
+    temperature: Option<f64>,
+    window: Option<ModelWindow>,
+    // Mean square current over the last complete window, in A².
+    heating: Option<f64>,
+    // Least squares sums for the gain, in °C/A².
+    sum_xy: f64,
+    sum_xx: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ModelWindow {
+    start: Instant,
+    start_temperature: f64,
+    sum_square_current: f64,
+    sum_temperature: f64,
+    samples: u32,
+}
+
+impl MotorModel {
+    pub fn new(time_constant: Duration) -> Self {
+        Self { time_constant, ambient: None, temperature: None, window: None, heating: None, sum_xy: 0.0, sum_xx: 0.0 }
+    }
+
+    /// Forget everything learnt, such as when the drive is disconnected and could come back on a different motor.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.time_constant);
+    }
+
+    /// Add a reading of the motor. Readings at the bottom of the sensor range, as sent before the drive has reported
+    /// anything, are ignored.
+    pub fn update(&mut self, now: Instant, current: Current, temperature: MotorTemperature) {
+        if temperature <= MotorTemperature::default() {
+            return;
+        }
+
+        let current = f64::from(current.0) / 1_000.0;
+        let temperature = temperature.to_celsius_f64();
+
+        let ambient = *self.ambient.get_or_insert(temperature);
+        self.temperature = Some(temperature);
+
+        let window = self.window.get_or_insert(ModelWindow {
+            start: now,
+            start_temperature: temperature,
+            sum_square_current: 0.0,
+            sum_temperature: 0.0,
+            samples: 0,
+        });
+        window.sum_square_current += current * current;
+        window.sum_temperature += temperature;
+        window.samples += 1;
+
+        let elapsed = now.duration_since(window.start);
+        if elapsed < MODEL_WINDOW {
+            return;
+        }
+
+        // Rearranging the model, time constant * dT/dt + (T - ambient) = gain * I².
+        let heating = window.sum_square_current / f64::from(window.samples);
+        let mean_temperature = window.sum_temperature / f64::from(window.samples);
+        let rate = (temperature - window.start_temperature) / elapsed.as_secs_f64();
+        let rise = self.time_constant.as_secs_f64() * rate + (mean_temperature - ambient);
+
+        self.sum_xy = MODEL_FORGETTING * self.sum_xy + heating * rise;
+        self.sum_xx = MODEL_FORGETTING * self.sum_xx + heating * heating;
+        self.heating = Some(heating);
+        self.window = None;
+    }
+
+    /// How long until the temperature reaches the limit at the recent current, or None if it never will.
+    pub fn time_to_limit(&self, limit: MotorTemperature) -> Option<Duration> {
+        let (ambient, temperature, heating) = (self.ambient?, self.temperature?, self.heating?);
+        if self.sum_xx <= f64::EPSILON {
+            return None;
+        }
+
+        let limit = limit.to_celsius_f64();
+        if temperature >= limit {
+            return Some(Duration::ZERO);
+        }
+
+        let steady = ambient + heating * self.sum_xy / self.sum_xx;
+        if steady <= limit {
+            return None;
+        }
+
+        let remaining = self.time_constant.as_secs_f64() * ((steady - temperature) / (steady - limit)).ln();
+        Some(Duration::from_secs_f64(remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ThermalLimits = ThermalLimits {
+        drive: Thresholds {
+            derate: DriveTemperature::from_celsius_f64(60.0),
+            pause: DriveTemperature::from_celsius_f64(70.0),
+            resume: DriveTemperature::from_celsius_f64(55.0),
+        },
+        motor: Thresholds {
+            derate: MotorTemperature::from_celsius_f64(70.0),
+            pause: MotorTemperature::from_celsius_f64(90.0),
+            resume: MotorTemperature::from_celsius_f64(60.0),
+        },
+        minimum_derating: 50,
+        auto_resume: true,
+    };
+
+    fn status(previous: ThermalStatus, drive: f64, motor: f64) -> ThermalStatus {
+        LIMITS.status(
+            previous,
+            DriveTemperature::from_celsius_f64(drive),
+            MotorTemperature::from_celsius_f64(motor),
+            WarningFlags::empty(),
+        )
+    }
+
+    #[test]
+    fn derating_follows_the_hotter_sensor() {
+        assert_eq!(status(ThermalStatus::default(), 40.0, 40.0), ThermalStatus::default());
+        assert_eq!(status(ThermalStatus::default(), 65.0, 40.0).derating, 75);
+        assert_eq!(status(ThermalStatus::default(), 65.0, 85.0).derating, 63);
+        assert_eq!(status(ThermalStatus::default(), 40.0, 85.0), ThermalStatus { derating: 63, paused: false });
+    }
+
+    #[test]
+    fn limits_must_pause_above_derate_and_resume() {
+        assert!(LIMITS.is_valid());
+
+        let derate_at_pause = Thresholds { derate: LIMITS.drive.pause, ..LIMITS.drive };
+        assert!(!ThermalLimits { drive: derate_at_pause, ..LIMITS }.is_valid());
+
+        let resume_above_pause = Thresholds { resume: MotorTemperature::from_celsius_f64(95.0), ..LIMITS.motor };
+        assert!(!ThermalLimits { motor: resume_above_pause, ..LIMITS }.is_valid());
+
+        let beyond_sensor = Thresholds { pause: MotorTemperature::from_celsius_f64(250.0), ..LIMITS.motor };
+        assert!(!ThermalLimits { motor: beyond_sensor, ..LIMITS }.is_valid());
+
+        assert!(!ThermalLimits { minimum_derating: 120, ..LIMITS }.is_valid());
+    }
+
+    #[test]
+    fn pause_holds_until_cooled_to_resume_temperature() {
+        let paused = status(ThermalStatus::default(), 70.0, 40.0);
+        assert_eq!(paused, ThermalStatus { derating: 50, paused: true });
+
+        // Below the pause temperature but still above the resume temperature.
+        assert!(status(paused, 58.0, 40.0).paused);
+        assert_eq!(status(paused, 54.0, 40.0), ThermalStatus::default());
+    }
+
+    #[test]
+    fn hot_warning_pauses_regardless_of_temperature() {
+        let status = LIMITS.status(
+            ThermalStatus::default(),
+            DriveTemperature::from_celsius_f64(30.0),
+            MotorTemperature::from_celsius_f64(30.0),
+            WarningFlags::MOTOR_HOT_SENSOR,
+        );
+
+        assert_eq!(status, ThermalStatus { derating: 100, paused: true });
+    }
+
+    // Simulates a motor with a known gain and time constant, sampled like the drive feedback.
+    fn simulate(model: &mut MotorModel, start: Instant, duration: Duration, current: f64) -> f64 {
+        const GAIN: f64 = 5.0;
+        const AMBIENT: f64 = 25.0;
+        const STEP: Duration = Duration::from_millis(100);
+
+        let mut temperature = AMBIENT;
+        let mut time = Duration::ZERO;
+        while time < duration {
+            temperature += (GAIN * current * current - (temperature - AMBIENT)) / 600.0 * STEP.as_secs_f64();
+            model.update(
+                start + time,
+                Current((current * 1_000.0) as i16),
+                MotorTemperature::from_celsius_f64(temperature),
+            );
+            time += STEP;
+        }
+
+        temperature
+    }
+
+    #[test]
+    fn motor_model_predicts_time_to_limit() {
+        let start = Instant::now();
+        let mut model = MotorModel::new(Duration::from_secs(600));
+        assert_eq!(model.time_to_limit(MotorTemperature::from_celsius_f64(90.0)), None);
+
+        // Heading for 105°C, reached 90°C about 404s after this point.
+        let temperature = simulate(&mut model, start, Duration::from_secs(600), 4.0);
+        assert!((temperature - 75.6).abs() < 0.1);
+
+        let remaining = model.time_to_limit(MotorTemperature::from_celsius_f64(90.0)).unwrap();
+        assert!((340..470).contains(&remaining.as_secs()), "{:?}", remaining);
+
+        // Never gets as far as 110°C.
+        assert_eq!(model.time_to_limit(MotorTemperature::from_celsius_f64(110.0)), None);
+    }
+
+    #[test]
+    fn motor_model_never_reaches_limit_at_low_current() {
+        let start = Instant::now();
+        let mut model = MotorModel::new(Duration::from_secs(600));
+
+        // Heading for 70°C.
+        simulate(&mut model, start, Duration::from_secs(600), 3.0);
+        assert_eq!(model.time_to_limit(MotorTemperature::from_celsius_f64(90.0)), None);
+        assert!(model.time_to_limit(MotorTemperature::from_celsius_f64(55.0)).is_some());
+    }
+
+    #[test]
+    fn motor_model_ignores_missing_readings() {
+        let start = Instant::now();
+        let mut model = MotorModel::new(Duration::from_secs(600));
+        let limit = MotorTemperature::from_celsius_f64(90.0);
+
+        // Readings from before the drive reports anything don't pin the ambient temperature at the sensor minimum.
+        model.update(start, Current(0), MotorTemperature::default());
+        simulate(&mut model, start, Duration::from_secs(600), 4.0);
+        let remaining = model.time_to_limit(limit).unwrap();
+        assert!((340..470).contains(&remaining.as_secs()), "{:?}", remaining);
+
+        for tick in 0..200 {
+            model.update(start + Duration::from_millis(600_000 + tick * 100), Current(0), MotorTemperature::default());
+        }
+        assert_eq!(model.time_to_limit(limit), Some(remaining));
+
+        model.reset();
+        assert_eq!(model.time_to_limit(limit), None);
+    }
+}
@@ -0,0 +1,343 @@
+use crate::drive::HANDOFF_TICKS;
+use crate::profile::{meters, meters_per_second, meters_per_second_squared};
+use puddle::messages::MotionCommand;
+use std::time::Duration;
+
+// Follows a command set through the drive's VAI profile generator. Each leg runs the exact trapezoidal profile
+// from wherever the previous leg left off, checked at every drive loop tick for an early handoff to the next command
+// the same way the drive loop does it. Calculations are in SI units.
+
+// Closer than this to the target counts as on it, well below the drive's position resolution.
+const ON_TARGET: f64 = 1e-9;
+
+// Longest a leg may take to arrive, in seconds. Slower legs are rejected rather than followed tick by tick.
+const MAX_LEG_DURATION: f64 = 600.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MotionState {
+    pub position: f64,
+    pub velocity: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Leg {
+    // From the start of the leg to the next one taking over, including any dwell.
+    pub duration: Duration,
+    // Fastest the leg goes, in m/s.
+    pub peak_velocity: f64,
+    // Whether the leg gets up to its command velocity.
+    pub reaches_velocity: bool,
+    // Whether the next leg takes over before this one comes to a stop on its target.
+    pub early_handoff: bool,
+    // Where the next leg starts from.
+    pub end: MotionState,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub legs: Vec<Leg>,
+    pub cycle_time: Duration,
+}
+
+// A stretch of constant acceleration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Phase {
+    duration: f64,
+    velocity: f64,
+    acceleration: f64,
+}
+
+/// Time each leg of a repeating command set, once it has settled into its cycle.
+///
+/// The first pass starts from a standstill on the last command's target, and the second pass, starting from wherever
+/// the first handed off, is the one reported. Returns None if the set is empty, or a leg would never get anywhere or
+/// would take too long to.
+pub fn analyze(commands: &[MotionCommand], interval: Duration) -> Option<Analysis> {
+    let mut state = MotionState { position: meters(commands.last()?.position), velocity: 0.0 };
+
+    let mut legs = Vec::with_capacity(commands.len());
+    for _ in 0..2 {
+        legs.clear();
+        for (index, command) in commands.iter().enumerate() {
+            let previous = &commands[(index + commands.len() - 1) % commands.len()];
+            let leg = leg(state, meters(previous.position), command, interval)?;
+            state = leg.end;
+            legs.push(leg);
+        }
+    }
+
+    let cycle_time = legs.iter().map(|leg| leg.duration).sum();
+    Some(Analysis { legs, cycle_time })
+}
+
+/// Run one leg from the start state, heading from the previous target towards the command's, until the next command
+/// would take over, or None if it would never get there or would take too long.
+pub fn leg(start: MotionState, previous: f64, command: &MotionCommand, interval: Duration) -> Option<Leg> {
+    let target = meters(command.position);
+    let (velocity, acceleration, deceleration) = (
+        meters_per_second(command.velocity),
+        meters_per_second_squared(command.acceleration),
+        meters_per_second_squared(command.deceleration),
+    );
+
+    let phases = phases(start, target, velocity, acceleration, deceleration)?;
+    let total: f64 = phases.iter().map(|phase| phase.duration).sum();
+    if total > MAX_LEG_DURATION {
+        return None;
+    }
+
+    // Dwelling commands are always stopped on exactly, as the drive never hands them off early.
+    let handoff = if command.dwell > 0 {
+        total
+    } else {
+        handoff_time(&phases, start, previous, target, total, interval.as_secs_f64())
+    };
+
+    let end = state_at(&phases, start, handoff);
+    let peak_velocity = phases
+        .iter()
+        .scan(0.0, |elapsed, phase| {
+            let started = *elapsed;
+            *elapsed += phase.duration;
+            (started < handoff).then_some(phase.velocity)
+        })
+        .chain([end.velocity])
+        .map(f64::abs)
+        .fold(0.0, f64::max);
+
+    Some(Leg {
+        duration: Duration::from_secs_f64(handoff) + Duration::from_millis(command.dwell.into()),
+        peak_velocity,
+        reaches_velocity: peak_velocity >= velocity * (1.0 - 1e-9),
+        early_handoff: handoff < total,
+        end,
+    })
+}
+
+// The VAI profile from the start state to a standstill on the target. Heading away from the target, or too fast to
+// stop on it, brakes to a standstill at the deceleration first and goes on from there.
+fn phases(start: MotionState, target: f64, velocity: f64, acceleration: f64, deceleration: f64) -> Option<Vec<Phase>> {
+    let displacement = target - start.position;
+    if displacement.abs() <= ON_TARGET && start.velocity == 0.0 {
+        return Some(Vec::new());
+    }
+    if velocity <= 0.0 || acceleration <= 0.0 || deceleration <= 0.0 {
+        return None;
+    }
+
+    let direction = if displacement.abs() > ON_TARGET { displacement.signum() } else { -start.velocity.signum() };
+    let (mut distance, mut speed) = (displacement.abs(), start.velocity * direction);
+
+    if speed < 0.0 || speed * speed / (2.0 * deceleration) > distance + ON_TARGET {
+        let brake = Phase {
+            duration: speed.abs() / deceleration,
+            velocity: start.velocity,
+            acceleration: -start.velocity.signum() * deceleration,
+        };
+        let stopped = MotionState { position: start.position + start.velocity * brake.duration / 2.0, velocity: 0.0 };
+
+        let mut phases = vec![brake];
+        phases.extend(self::phases(stopped, target, velocity, acceleration, deceleration)?);
+        return Some(phases);
+    }
+
+    let mut phases = Vec::with_capacity(4);
+    if speed > velocity {
+        let duration = (speed - velocity) / deceleration;
+        phases.push(Phase { duration, velocity: speed * direction, acceleration: -deceleration * direction });
+        distance -= (speed + velocity) / 2.0 * duration;
+        speed = velocity;
+    }
+
+    let ramps = |peak: f64| (peak * peak - speed * speed) / (2.0 * acceleration) + peak * peak / (2.0 * deceleration);
+    let peak = if ramps(velocity) <= distance {
+        velocity
+    } else {
+        // Never reaches the velocity, turning around where the two ramps meet.
+        ((2.0 * acceleration * deceleration * distance + deceleration * speed * speed) / (acceleration + deceleration))
+            .sqrt()
+            .max(speed)
+    };
+
+    phases.push(Phase {
+        duration: (peak - speed) / acceleration,
+        velocity: speed * direction,
+        acceleration: acceleration * direction,
+    });
+    phases.push(Phase {
+        duration: (distance - ramps(peak)).max(0.0) / peak,
+        velocity: peak * direction,
+        acceleration: 0.0,
+    });
+    phases.push(Phase {
+        duration: peak / deceleration,
+        velocity: peak * direction,
+        acceleration: -deceleration * direction,
+    });
+
+    phases.retain(|phase| phase.duration > 0.0);
+    Some(phases)
+}
+
+fn state_at(phases: &[Phase], start: MotionState, time: f64) -> MotionState {
+    let mut state = start;
+    let mut remaining = time;
+    for phase in phases {
+        let duration = phase.duration.min(remaining);
+        state.position += phase.velocity * duration + phase.acceleration * duration * duration / 2.0;
+        state.velocity = phase.velocity + phase.acceleration * duration;
+
+        remaining -= duration;
+        if remaining <= 0.0 {
+            break;
+        }
+    }
+
+    state
+}
+
+fn acceleration_at(phases: &[Phase], time: f64) -> f64 {
+    let mut elapsed = 0.0;
+    for phase in phases {
+        elapsed += phase.duration;
+        if time < elapsed {
+            return phase.acceleration;
+        }
+    }
+
+    0.0
+}
+
+// The first drive loop tick at which the drive would treat the target as reached, predicting ahead by the same
+// horizon and with the same conditions as the drive loop. Without a handoff, the leg ends once stopped on the target.
+fn handoff_time(phases: &[Phase], start: MotionState, previous: f64, target: f64, total: f64, interval: f64) -> f64 {
+    let horizon = interval * f64::from(HANDOFF_TICKS);
+    let approach = if (target - previous).abs() > ON_TARGET { (target - previous).signum() } else { 0.0 };
+
+    if interval <= 0.0 {
+        return total;
+    }
+
+    let mut approached = false;
+    for tick in 0..=(total / interval).ceil() as u32 {
+        let time = f64::from(tick) * interval;
+        if time >= total {
+            break;
+        }
+
+        let MotionState { position, velocity } = state_at(phases, start, time);
+        let acceleration = acceleration_at(phases, time);
+
+        let displacement = target - position;
+        if displacement.abs() <= ON_TARGET {
+            return time;
+        }
+
+        let direction = if approach != 0.0 { approach } else { displacement.signum() };
+        let moving_toward = velocity * direction > 0.0;
+        let decelerating = velocity * acceleration < 0.0;
+        if moving_toward && !decelerating {
+            approached = true;
+        }
+
+        let after = target - (position + velocity * horizon + acceleration * horizon * horizon / 2.0);
+        let crosses = displacement * after <= 0.0;
+        let will_stop = moving_toward && decelerating && velocity * (velocity + acceleration * horizon) <= 0.0;
+
+        if crosses || (approached && (will_stop || velocity == 0.0 || !moving_toward)) {
+            return time;
+        }
+    }
+
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use puddle::units::{Acceleration, Position, Velocity};
+
+    const INTERVAL: Duration = Duration::from_millis(2);
+
+    fn command(millimeters: i32, velocity: f64, acceleration: f64, dwell: u32) -> MotionCommand {
+        MotionCommand {
+            position: Position::from_millimeters(millimeters),
+            velocity: Velocity::from_meters_per_second_f64(velocity),
+            acceleration: Acceleration::from_meters_per_second_squared_f64(acceleration),
+            deceleration: Acceleration::from_meters_per_second_squared_f64(acceleration),
+            dwell,
+        }
+    }
+
+    #[test]
+    fn dwelling_legs_run_rest_to_rest() {
+        // Each way is 0.25s up to 1m/s, 0.75s cruising and 0.25s down, then half a second of dwell.
+        let analysis = analyze(&[command(0, 1.0, 4.0, 500), command(1_000, 1.0, 4.0, 500)], INTERVAL).unwrap();
+
+        assert!((analysis.cycle_time.as_secs_f64() - 3.5).abs() < 1e-6);
+        for leg in &analysis.legs {
+            assert!((leg.duration.as_secs_f64() - 1.75).abs() < 1e-6);
+            assert!((leg.peak_velocity - 1.0).abs() < 1e-9);
+            assert!(leg.reaches_velocity && !leg.early_handoff);
+            assert_eq!(leg.end.velocity, 0.0);
+        }
+    }
+
+    #[test]
+    fn short_legs_never_reach_velocity() {
+        // Peaks at 0.4m/s halfway along the 40mm.
+        let analysis = analyze(&[command(0, 1.0, 4.0, 100), command(40, 1.0, 4.0, 100)], INTERVAL).unwrap();
+
+        let leg = analysis.legs[1];
+        assert!((leg.peak_velocity - 0.4).abs() < 1e-9);
+        assert!(!leg.reaches_velocity);
+        assert!((leg.duration.as_secs_f64() - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pass_through_legs_hand_off_early() {
+        let commands = [command(0, 1.0, 4.0, 0), command(500, 1.0, 4.0, 0)];
+        let analysis = analyze(&commands, INTERVAL).unwrap();
+
+        // Handing off as the drive predicts the stop within the horizon, still moving slowly.
+        for leg in &analysis.legs {
+            assert!(leg.early_handoff);
+            assert!(leg.end.velocity.abs() > 0.0 && leg.end.velocity.abs() <= 4.0 * 0.006 + 1e-9);
+        }
+        assert!((analysis.cycle_time.as_secs_f64() - 1.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn braking_after_a_handoff_carries_on_the_previous_direction() {
+        // Still heading away at 0.5m/s, so it takes 0.125s to stop 31.25mm further on before turning around.
+        let start = MotionState { position: 0.0, velocity: -0.5 };
+        let leg = leg(start, 0.0, &command(100, 1.0, 4.0, 10), INTERVAL).unwrap();
+
+        let back = crate::profile::rest_to_rest_duration(
+            Position::from_millimeters_f64(131.25),
+            Velocity::from_meters_per_second(1),
+            Acceleration::from_meters_per_second_squared(4),
+            Acceleration::from_meters_per_second_squared(4),
+        )
+        .unwrap();
+        let expected = Duration::from_millis(125) + back + Duration::from_millis(10);
+        assert!((leg.duration.as_secs_f64() - expected.as_secs_f64()).abs() < 1e-6);
+        assert!((leg.end.position - 0.1).abs() < 1e-9 && leg.end.velocity == 0.0);
+    }
+
+    #[test]
+    fn legs_that_never_move_are_rejected() {
+        assert_eq!(analyze(&[], INTERVAL), None);
+        assert_eq!(analyze(&[command(0, 0.0, 4.0, 0), command(100, 0.0, 4.0, 0)], INTERVAL), None);
+    }
+
+    #[test]
+    fn slow_long_legs_are_rejected() {
+        // 300mm at 1µm/s would take over three days.
+        assert_eq!(analyze(&[command(0, 1e-6, 4.0, 0), command(300, 1e-6, 4.0, 0)], INTERVAL), None);
+
+        // Ten seconds is fine.
+        let analysis = analyze(&[command(0, 0.03, 4.0, 0), command(300, 0.03, 4.0, 0)], INTERVAL).unwrap();
+        assert!((analysis.cycle_time.as_secs_f64() - 20.0).abs() < 0.1);
+    }
+}
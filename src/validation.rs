@@ -0,0 +1,179 @@
+use puddle::SystemLimits;
+use puddle::messages::{
+    CommandField, CommandIssue, CommandIssueKind, CommandSetCaps, MotionCommand, PositionGuards, WorkCoordinates,
+};
+use puddle::units::Position;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Issues {
+    pub errors: Vec<CommandIssue>,
+    pub warnings: Vec<CommandIssue>,
+}
+
+/// Check a command set before storing it.
+///
+/// Values beyond the system limits, as narrowed by the set's caps, are errors, as the drive could only run them
+/// clamped. Anything else that will run differently to how it reads is returned as warnings: positions clamped to the
+/// position guards' soft limits, legs that never arrive, and legs that go nowhere or are shorter than the overshoot
+/// margin.
+pub fn check(
+    commands: &[MotionCommand],
+    limits: &SystemLimits,
+    caps: &CommandSetCaps,
+    coordinates: WorkCoordinates,
+    guards: &PositionGuards,
+    overshoot_margin: Position,
+) -> Issues {
+    let (lowest, highest) = caps.stroke(limits, coordinates);
+    let limits = caps.narrow(limits);
+
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+
+    for (index, command) in commands.iter().enumerate() {
+        let issue = |field, issue| CommandIssue { index, field, issue };
+
+        let position = coordinates.to_drive(command.position);
+        let stroke = position.clamp(lowest, highest);
+        if stroke != position {
+            let limit = coordinates.from_drive(stroke).0;
+            errors.push(issue(CommandField::Position, CommandIssueKind::OutOfLimits { limit }));
+        } else {
+            // Kept within the soft limits in the same way as when the commands are sent to the drive.
+            let (lowest, highest) = guards.narrow((lowest, highest));
+            let guarded = position.clamp(lowest, highest);
+            if guarded != position {
+                let value = coordinates.from_drive(guarded).0;
+                warnings.push(issue(CommandField::Position, CommandIssueKind::Clamped { value }));
+            }
+        }
+
+        for (field, value, limit) in [
+            (CommandField::Velocity, command.velocity.0, limits.velocity.0),
+            (CommandField::Acceleration, command.acceleration.0, limits.acceleration.0),
+            (CommandField::Deceleration, command.deceleration.0, limits.deceleration.0),
+        ] {
+            if value < 0 {
+                errors.push(issue(field, CommandIssueKind::OutOfLimits { limit: 0 }));
+            } else if value > limit {
+                errors.push(issue(field, CommandIssueKind::OutOfLimits { limit }));
+            } else if value == 0 {
+                warnings.push(issue(field, CommandIssueKind::Zero));
+            }
+        }
+
+        // Legs run from the previous command's target, with the first carrying on from the last.
+        if commands.len() > 1 {
+            let previous = &commands[(index + commands.len() - 1) % commands.len()];
+            let distance = command.position.0.abs_diff(previous.position.0);
+            if distance == 0 {
+                warnings.push(issue(CommandField::Position, CommandIssueKind::DuplicatePosition));
+            } else if distance < overshoot_margin.0.unsigned_abs() {
+                warnings.push(issue(CommandField::Position, CommandIssueKind::ShortLeg));
+            }
+        }
+    }
+
+    Issues { errors, warnings }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use puddle::units::{Acceleration, Velocity};
+
+    const LIMITS: SystemLimits = SystemLimits {
+        position: Position::from_millimeters(360),
+        velocity: Velocity::from_meters_per_second(2),
+        acceleration: Acceleration::from_meters_per_second_squared(15),
+        deceleration: Acceleration::from_meters_per_second_squared(15),
+    };
+
+    fn command(millimeters: i32, velocity: i32) -> MotionCommand {
+        MotionCommand {
+            position: Position::from_millimeters(millimeters),
+            velocity: Velocity::from_meters_per_second(velocity),
+            acceleration: Acceleration::from_meters_per_second_squared(10),
+            deceleration: Acceleration::from_meters_per_second_squared(10),
+            dwell: 0,
+        }
+    }
+
+    fn check(commands: &[MotionCommand], guards: &PositionGuards) -> Issues {
+        let caps = CommandSetCaps::default();
+        super::check(commands, &LIMITS, &caps, WorkCoordinates::default(), guards, Position::from_millimeters(1))
+    }
+
+    #[test]
+    fn values_beyond_the_limits_are_errors() {
+        let issues = check(&[command(100, 5), command(400, 1)], &PositionGuards::default());
+
+        assert_eq!(issues.warnings, []);
+        assert_eq!(
+            issues.errors,
+            [
+                CommandIssue {
+                    index: 0,
+                    field: CommandField::Velocity,
+                    issue: CommandIssueKind::OutOfLimits { limit: LIMITS.velocity.0 },
+                },
+                CommandIssue {
+                    index: 1,
+                    field: CommandField::Position,
+                    issue: CommandIssueKind::OutOfLimits { limit: LIMITS.position.0 },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn questionable_commands_are_warnings() {
+        let guards = PositionGuards { maximum: Some(Position::from_millimeters(300)), ..PositionGuards::default() };
+        let commands = [command(100, 1), command(100, 0), command(320, 1), command(100, 1)];
+        let Issues { errors, warnings } = check(&commands, &guards);
+        assert_eq!(errors, []);
+
+        let issues: Vec<_> = warnings.iter().map(|warning| (warning.index, warning.field, warning.issue)).collect();
+        assert_eq!(
+            issues,
+            [
+                (0, CommandField::Position, CommandIssueKind::DuplicatePosition),
+                (1, CommandField::Velocity, CommandIssueKind::Zero),
+                (1, CommandField::Position, CommandIssueKind::DuplicatePosition),
+                (2, CommandField::Position, CommandIssueKind::Clamped { value: Position::from_millimeters(300).0 }),
+            ]
+        );
+
+        // Shorter than the 1mm overshoot margin.
+        let commands =
+            [command(100, 1), MotionCommand { position: Position::from_millimeters_f64(100.5), ..command(0, 1) }];
+        let warnings = check(&commands, &PositionGuards::default()).warnings;
+        assert!(warnings.iter().all(|warning| warning.issue == CommandIssueKind::ShortLeg));
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn caps_narrow_the_limits() {
+        let caps = CommandSetCaps {
+            velocity: Some(Velocity::from_meters_per_second_f64(0.5)),
+            minimum: Some(Position::from_millimeters(-50)),
+            maximum: Some(Position::from_millimeters(100)),
+            ..CommandSetCaps::default()
+        };
+        let coordinates = WorkCoordinates { offset: Position::from_millimeters(200), inverted: true };
+
+        // Work positions from -50mm to 100mm are drive positions from 250mm down to 100mm.
+        let commands = [command(0, 1), command(-100, 0)];
+        let issues =
+            super::check(&commands, &LIMITS, &caps, coordinates, &PositionGuards::default(), Position::default());
+
+        let errors: Vec<_> = issues.errors.iter().map(|error| (error.index, error.field, error.issue)).collect();
+        assert_eq!(
+            errors,
+            [
+                (0, CommandField::Velocity, CommandIssueKind::OutOfLimits { limit: caps.velocity.unwrap().0 }),
+                (1, CommandField::Position, CommandIssueKind::OutOfLimits { limit: Position::from_millimeters(-50).0 }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use puddle::messages::{MotionCommand, Variation};
+use puddle::units::{Acceleration, Position, Velocity};
+
+/// Follows the drive through the active set to work out which pass each command's variation is drawn for.
+///
+/// Commands behind the drive are next run on the following pass, so are varied afresh as soon as it leaves them,
+/// rather than while it is heading for them.
+#[derive(Debug, Default)]
+pub struct Passes {
+    version: u64,
+    pass: u32,
+    // Command index last seen, or None until the drive has started on this version of the set.
+    index: Option<usize>,
+}
+
+impl Passes {
+    /// Account for the drive's command index, returning whether any command's pass changed.
+    pub fn update(&mut self, version: u64, index: usize) -> bool {
+        let mut changed = false;
+        if version != self.version {
+            *self = Self { version, ..Self::default() };
+            changed = true;
+        }
+
+        match self.index {
+            // Until the drive starts over, the index may still be that of the previous set.
+            None if index != 0 => {}
+            None => self.index = Some(index),
+            Some(previous) if previous != index => {
+                if index < previous {
+                    self.pass = self.pass.wrapping_add(1);
+                }
+                self.index = Some(index);
+                changed = true;
+            }
+            Some(_) => {}
+        }
+
+        changed
+    }
+
+    /// The pass the command at this index will next run in.
+    pub fn pass(&self, index: usize) -> u32 {
+        self.pass.wrapping_add(u32::from(self.index.is_some_and(|current| index < current)))
+    }
+}
+
+/// Vary a command for a pass through the set. The same seed, pass and index always give the same variation.
+pub fn vary(command: &MotionCommand, variation: &Variation, index: usize, pass: u32) -> MotionCommand {
+    let draw = |field: u64| unit(variation.seed, pass, index, field);
+    let factor = |field, percent: u16| 1.0 + draw(field) * f64::from(percent) / 100.0;
+    let scale = |value: i32, factor: f64| (f64::from(value) * factor).round() as i32;
+
+    // Acceleration and deceleration vary together, keeping the shape of each leg.
+    let (velocity, acceleration) = (factor(1, variation.velocity), factor(2, variation.acceleration));
+
+    MotionCommand {
+        position: Position(command.position.0.saturating_add(scale(variation.position.0, draw(0)))),
+        velocity: Velocity(scale(command.velocity.0, velocity)),
+        acceleration: Acceleration(scale(command.acceleration.0, acceleration)),
+        deceleration: Acceleration(scale(command.deceleration.0, acceleration)),
+        dwell: command.dwell,
+    }
+}
+
+// A number spread evenly between -1 and 1, from hashing the inputs together with SplitMix64's finaliser.
+fn unit(seed: u64, pass: u32, index: usize, field: u64) -> f64 {
+    let hash = [u64::from(pass), index as u64, field].into_iter().fold(mix(seed), |hash, value| mix(hash ^ value));
+    (hash >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
+
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{meters, meters_per_second, meters_per_second_squared};
+
+    const VARIATION: Variation =
+        Variation { position: Position::from_millimeters(10), velocity: 20, acceleration: 50, seed: 42 };
+
+    fn command() -> MotionCommand {
+        MotionCommand {
+            position: Position::from_millimeters(100),
+            velocity: Velocity::from_meters_per_second(1),
+            acceleration: Acceleration::from_meters_per_second_squared(4),
+            deceleration: Acceleration::from_meters_per_second_squared(2),
+            dwell: 0,
+        }
+    }
+
+    #[test]
+    fn variation_is_bounded_and_reproducible() {
+        let varied: Vec<_> = (0..100).map(|pass| vary(&command(), &VARIATION, 0, pass)).collect();
+        assert_eq!(varied, (0..100).map(|pass| vary(&command(), &VARIATION, 0, pass)).collect::<Vec<_>>());
+
+        for command in &varied {
+            assert!((0.09..=0.11).contains(&meters(command.position)));
+            assert!((0.8..=1.2).contains(&meters_per_second(command.velocity)));
+            assert!((2.0..=6.0).contains(&meters_per_second_squared(command.acceleration)));
+            assert!((command.acceleration.0 - command.deceleration.0 * 2).abs() <= 1);
+        }
+
+        // Different every pass, and for another seed.
+        assert!(varied.windows(2).all(|pair| pair[0].position != pair[1].position));
+        assert_ne!(vary(&command(), &Variation { seed: 43, ..VARIATION }, 0, 0), varied[0]);
+    }
+
+    #[test]
+    fn passes_move_on_behind_the_drive() {
+        let mut passes = Passes::default();
+        assert!(passes.update(1, 2), "a new version");
+        assert_eq!(passes.pass(0), 0);
+
+        // Still on the previous set's index, then reset onto the first command.
+        assert!(!passes.update(1, 0));
+        assert!(passes.update(1, 1));
+        assert_eq!((passes.pass(0), passes.pass(1), passes.pass(2)), (1, 0, 0));
+
+        assert!(passes.update(1, 0));
+        assert_eq!((passes.pass(0), passes.pass(1), passes.pass(2)), (1, 1, 1));
+        assert!(!passes.update(1, 0));
+    }
+}